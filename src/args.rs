@@ -0,0 +1,169 @@
+//! Command line flags.
+
+use crate::lighting::Quality;
+use pong::simulation::check_tick_rate;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "usage: pong [--host <port> | --join <address:port> | --connect <address:port> | --watch <address:port>]
+            [--replay <file>] [--seed <number>] [--tick-rate <ticks>]
+            [--size <width>x<height>] [--fullscreen | --windowed] [--vsync | --no-vsync]
+            [--msaa <samples>] [--title <text>] [--lighting <quality>] [--light-rays <number>]
+            [--max-lights <number>] [--dev]
+
+    --host <port>              wait for another player to join on this UDP port
+    --join <address:port>      play online against someone who is hosting
+    --connect <address:port>   play on a pong-server
+    --watch <address:port>     watch the match on a pong-server or someone hosting
+    --replay <file>            watch a recorded match
+    --seed <number>            start every match from this seed, so the serves come out the same
+    --tick-rate <ticks>        how many times a second the game logic runs, 30 to 240 (default 120)
+    --dev                      reload the shaders in resources when they're saved and show their errors
+
+These change the [graphics] table of settings.toml for this run only:
+    --size <width>x<height>    size of the window, e.g. 1280x720
+    --fullscreen, --windowed   start on the whole screen or in a window
+    --vsync, --no-vsync        wait for the screen to refresh before showing a frame
+    --msaa <samples>           smooth edges with 1, 2, 4, 8 or 16 samples per pixel
+    --title <text>             the title of the window
+    --lighting <quality>       how good the light and shadows look: off, low, medium or high
+    --light-rays <number>      directions the light checks for shadows, more look better but cost more
+    --max-lights <number>      the most lights drawn at once, up to 16";
+
+/// How to start the game when it was asked for an online match
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetMode {
+    Host(u16),
+    Join(String),
+    /// Play on a dedicated `pong-server`
+    Connect(String),
+    /// Watch the match on a `pong-server` or a game that is hosting
+    Watch(String),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    pub net: Option<NetMode>,
+    /// A recorded match to watch
+    pub replay: Option<PathBuf>,
+    /// Overrides the seed from the settings file
+    pub seed: Option<u64>,
+    /// Overrides the tick rate from the settings file
+    pub tick_rate: Option<u32>,
+    /// Override the `[graphics]` settings
+    pub window_size: Option<(f32, f32)>,
+    pub fullscreen: Option<bool>,
+    pub vsync: Option<bool>,
+    pub msaa: Option<u32>,
+    pub title: Option<String>,
+    pub lighting: Option<Quality>,
+    pub light_rays: Option<u16>,
+    pub max_lights: Option<u8>,
+    /// Watch the shader files and swap in the new ones when they change
+    pub dev: bool,
+}
+
+impl Args {
+    /// Reads the flags the game was started with, bad flags print the usage and quit
+    pub fn parse() -> Args {
+        match Args::parse_from(env::args().skip(1)) {
+            Ok(args) => args,
+            Err(error) => {
+                eprintln!("{}\n\n{}", error, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    fn parse_from(mut flags: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut args = Args::default();
+        while let Some(flag) = flags.next() {
+            let mut value = || {
+                flags
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", flag))
+            };
+            match flag.as_str() {
+                "--host" => {
+                    let port = value()?;
+                    let port = port
+                        .parse()
+                        .map_err(|_| format!("{:?} is not a port", port))?;
+                    args.net = Some(NetMode::Host(port));
+                }
+                "--join" => args.net = Some(NetMode::Join(value()?)),
+                "--connect" => args.net = Some(NetMode::Connect(value()?)),
+                "--watch" => args.net = Some(NetMode::Watch(value()?)),
+                "--replay" => args.replay = Some(PathBuf::from(value()?)),
+                "--seed" => {
+                    let seed = value()?;
+                    let seed = seed
+                        .parse()
+                        .map_err(|_| format!("{:?} is not a seed", seed))?;
+                    args.seed = Some(seed);
+                }
+                "--tick-rate" => {
+                    let tick_rate = value()?;
+                    let tick_rate = tick_rate
+                        .parse()
+                        .map_err(|_| format!("{:?} is not a number of ticks", tick_rate))?;
+                    args.tick_rate = Some(check_tick_rate(tick_rate)?);
+                }
+                "--size" => {
+                    let size = value()?;
+                    let parsed = size
+                        .split_once('x')
+                        .and_then(|(w, h)| Some((w.parse::<f32>().ok()?, h.parse::<f32>().ok()?)));
+                    match parsed {
+                        Some((w, h)) if w > 0.0 && h > 0.0 => args.window_size = Some((w, h)),
+                        _ => return Err(format!("{:?} is not a size like 1280x720", size)),
+                    }
+                }
+                "--fullscreen" => args.fullscreen = Some(true),
+                "--windowed" => args.fullscreen = Some(false),
+                "--vsync" => args.vsync = Some(true),
+                "--no-vsync" => args.vsync = Some(false),
+                "--msaa" => {
+                    let samples = value()?;
+                    match samples.parse() {
+                        Ok(samples @ (1 | 2 | 4 | 8 | 16)) => args.msaa = Some(samples),
+                        _ => return Err(format!("{:?} is not 1, 2, 4, 8 or 16", samples)),
+                    }
+                }
+                "--title" => args.title = Some(value()?),
+                "--lighting" => {
+                    let quality = value()?;
+                    let quality = quality
+                        .parse()
+                        .map_err(|_| format!("{:?} is not off, low, medium or high", quality))?;
+                    args.lighting = Some(quality);
+                }
+                "--light-rays" => {
+                    let rays = value()?;
+                    let rays = rays
+                        .parse()
+                        .map_err(|_| format!("{:?} is not a number of rays", rays))?;
+                    args.light_rays = Some(rays);
+                }
+                "--max-lights" => {
+                    let lights = value()?;
+                    let lights = lights
+                        .parse()
+                        .map_err(|_| format!("{:?} is not a number of lights", lights))?;
+                    args.max_lights = Some(lights);
+                }
+                "--dev" => args.dev = true,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(format!("unknown flag {:?}", flag)),
+            }
+        }
+        if args.net.is_some() && args.replay.is_some() {
+            return Err("a replay can't be watched online".to_string());
+        }
+        Ok(args)
+    }
+}
@@ -0,0 +1,448 @@
+//! A headless server that runs matches for two remote players.
+//! It owns the only real simulation: clients send their racket input and get
+//! snapshots of the ball, rackets and score back, so nobody can cheat the score.
+//! Anyone else can watch, they get the same snapshots without a racket.
+
+use pong::discovery::{self, Announcement, Announcer, HostKind};
+use pong::protocol::{
+    ClientMessage, ServerMessage, Snapshot, DEFAULT_PORT, MAX_PACKET_SIZE, TIMEOUT,
+};
+use pong::replay::Replay;
+use pong::rules::{Ruleset, ServeRule};
+use pong::simulation::{
+    check_tick_rate, clamp, Event, Input, Player, Simulation, FIELD_H, FIELD_W, TICK_RATE,
+};
+use pong::spectators::Spectators;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+///a snapshot goes out every this many ticks
+const SNAPSHOT_INTERVAL: u32 = 2;
+///seconds between a match ending and the next one starting
+const NEXT_MATCH_DELAY: f32 = 5.0;
+
+const USAGE: &str = "usage: pong-server [options]
+
+    --port <port>          UDP port to listen on (default 7100)
+    --win-score <points>   points needed to win a game (default 11)
+    --no-win-by-two        a game doesn't have to be won by two clear points
+    --best-of <games>      how many games a match can last (default 1)
+    --serve <rule>         loser, alternate or random (default random)
+    --serve-angle <min-max>
+                           range of serve angles in degrees from straight across (default 15-45)
+    --serve-speed <speed>  average serve speed in pixels per second (default 382)
+    --serve-spread <speed> how much faster or slower serves can be (default 40)
+    --serve-to-conceder    serve at whoever lost the last point
+    --record <dir>         save a replay of every match in this directory
+    --seed <number>        start every match from this seed instead of a random one
+    --tick-rate <ticks>    how many times a second the matches run, 30 to 240 (default 120)";
+
+fn number<T: FromStr>(value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{:?} is not a number", value))
+}
+
+struct Options {
+    port: u16,
+    rules: Ruleset,
+    /// Where replays of the matches go, if anywhere
+    record: Option<PathBuf>,
+    /// Every match starts from this seed when it's set
+    seed: Option<u64>,
+    tick_rate: u32,
+}
+
+impl Options {
+    fn parse() -> Result<Options, String> {
+        let mut options = Options {
+            port: DEFAULT_PORT,
+            rules: Ruleset::default(),
+            record: None,
+            seed: None,
+            tick_rate: TICK_RATE,
+        };
+        let mut flags = env::args().skip(1);
+        while let Some(flag) = flags.next() {
+            let mut value = || {
+                flags
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value", flag))
+            };
+            match flag.as_str() {
+                "--port" => options.port = number(value()?)?,
+                "--win-score" => options.rules.win_score = number(value()?)?,
+                "--no-win-by-two" => options.rules.win_by_two = false,
+                "--best-of" => options.rules.best_of = number(value()?)?,
+                "--record" => options.record = Some(PathBuf::from(value()?)),
+                "--seed" => options.seed = Some(number(value()?)?),
+                "--tick-rate" => options.tick_rate = check_tick_rate(number(value()?)?)?,
+                "--serve" => {
+                    let name = value()?;
+                    options.rules.serve = match name.as_str() {
+                        "loser" => ServeRule::Loser,
+                        "alternate" => ServeRule::Alternate,
+                        "random" => ServeRule::Random,
+                        _ => return Err(format!("unknown serve rule {:?}", name)),
+                    };
+                }
+                "--serve-angle" => {
+                    let range = value()?;
+                    let (min, max) = range
+                        .split_once('-')
+                        .ok_or_else(|| format!("{:?} is not a range like 15-45", range))?;
+                    let model = &mut options.rules.serve_model;
+                    model.min_angle = number::<f32>(min.to_string())?.to_radians();
+                    model.max_angle = number::<f32>(max.to_string())?.to_radians();
+                }
+                "--serve-speed" => options.rules.serve_model.speed = number(value()?)?,
+                "--serve-spread" => options.rules.serve_model.speed_spread = number(value()?)?,
+                "--serve-to-conceder" => options.rules.serve_model.toward_conceder = true,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                _ => return Err(format!("unknown flag {:?}", flag)),
+            }
+        }
+        if options.rules.win_score == 0 || options.rules.best_of == 0 {
+            return Err("--win-score and --best-of have to be at least 1".to_string());
+        }
+        options.rules.serve_model = options.rules.serve_model.clamped();
+        Ok(options)
+    }
+}
+
+/// A fresh match, from `seed` if there is one
+fn match_simulation(rules: Ruleset, seed: Option<u64>) -> Simulation {
+    Simulation::with_seed(FIELD_W, FIELD_H, rules, seed.unwrap_or_else(rand::random))
+}
+
+/// Someone playing one of the rackets
+struct Client {
+    address: SocketAddr,
+    last_heard: Instant,
+    /// The newest input packet we've seen, older ones that arrive late are ignored
+    sequence: u32,
+    axis: f32,
+}
+
+struct Server {
+    socket: UdpSocket,
+    rules: Ruleset,
+    seed: Option<u64>,
+    /// How many times a second the match runs, the clients tick along at the same rate
+    tick_rate: u32,
+    /// The client playing each racket, player 1 first
+    clients: [Option<Client>; 2],
+    /// People watching without a racket
+    spectators: Spectators,
+    sim: Simulation,
+    /// The current match so far
+    recording: Replay,
+    /// Where finished matches get saved
+    record: Option<PathBuf>,
+    tick: u32,
+    /// Seconds left before the next match starts, once the current one is over
+    next_match: Option<f32>,
+    /// Lets games on the local network find us
+    announcer: Option<Announcer>,
+}
+
+impl Server {
+    fn new(options: &Options) -> io::Result<Server> {
+        let socket = UdpSocket::bind(("0.0.0.0", options.port))?;
+        socket.set_nonblocking(true)?;
+        let announcement = Announcement {
+            id: rand::random(),
+            kind: HostKind::Server,
+            name: discovery::host_name(),
+            port: socket.local_addr()?.port(),
+            rules: options.rules,
+            open_slots: 2,
+        };
+        // the server still works without it, people just have to type in the address
+        let announcer = match Announcer::new(announcement) {
+            Ok(announcer) => Some(announcer),
+            Err(e) => {
+                eprintln!("could not announce the server on the network: {}", e);
+                None
+            }
+        };
+        let sim = match_simulation(options.rules, options.seed);
+        Ok(Server {
+            socket,
+            rules: options.rules,
+            seed: options.seed,
+            tick_rate: options.tick_rate,
+            clients: [None, None],
+            spectators: Spectators::new(),
+            recording: Replay::new(&sim, options.tick_rate),
+            record: options.record.clone(),
+            sim,
+            tick: 0,
+            next_match: None,
+            announcer,
+        })
+    }
+
+    fn send(&self, address: SocketAddr, message: &ServerMessage) {
+        // lost packets are fine, a fresh snapshot is never far behind
+        let _ = self.socket.send_to(&message.encode(), address);
+    }
+
+    fn slot_of(&self, address: SocketAddr) -> Option<usize> {
+        self.clients
+            .iter()
+            .position(|client| client.as_ref().is_some_and(|c| c.address == address))
+    }
+
+    fn waiting(&self) -> bool {
+        self.clients.iter().any(Option::is_none)
+    }
+
+    fn new_match(&mut self) {
+        self.save_recording();
+        self.sim = match_simulation(self.rules, self.seed);
+        println!("new match with seed {}", self.sim.seed);
+        self.recording = Replay::new(&self.sim, self.tick_rate);
+        self.next_match = None;
+    }
+
+    /// Saves the match so far if we're recording, and there is anything to save
+    fn save_recording(&mut self) {
+        let dir = match &self.record {
+            Some(dir) if !self.recording.inputs.is_empty() => dir,
+            _ => return,
+        };
+        let path = Replay::new_path(dir);
+        match self.recording.save(&path) {
+            Ok(()) => println!("saved the replay to {}", path.display()),
+            Err(e) => eprintln!("could not save the replay to {}: {}", path.display(), e),
+        }
+        self.recording.inputs.clear();
+    }
+
+    fn receive(&mut self) {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let (size, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(_) => continue,
+            };
+            let message = match ClientMessage::decode(&buffer[..size]) {
+                Some(message) => message,
+                None => continue,
+            };
+            let slot = self.slot_of(from);
+            if let Some(slot) = slot {
+                if let Some(client) = &mut self.clients[slot] {
+                    client.last_heard = Instant::now();
+                }
+            }
+
+            match (message, slot) {
+                (ClientMessage::Join, slot) => {
+                    let slot = match slot.or_else(|| self.clients.iter().position(Option::is_none))
+                    {
+                        Some(slot) => slot,
+                        None => {
+                            self.send(from, &ServerMessage::Full);
+                            continue;
+                        }
+                    };
+                    if self.clients[slot].is_none() {
+                        println!("player {} joined from {}", slot + 1, from);
+                        self.clients[slot] = Some(Client {
+                            address: from,
+                            last_heard: Instant::now(),
+                            sequence: 0,
+                            axis: 0.0,
+                        });
+                        // everyone starts fresh once the second player turns up
+                        if !self.waiting() {
+                            self.new_match();
+                        }
+                    }
+                    let player = if slot == 0 { Player::One } else { Player::Two };
+                    self.send(
+                        from,
+                        &ServerMessage::Welcome {
+                            player,
+                            field_w: FIELD_W,
+                            field_h: FIELD_H,
+                            rules: self.rules,
+                            tick_rate: self.tick_rate,
+                        },
+                    );
+                }
+                (ClientMessage::Input { sequence, axis }, Some(slot)) => {
+                    if let Some(client) = &mut self.clients[slot] {
+                        if sequence >= client.sequence {
+                            client.sequence = sequence;
+                            // don't trust the client to stick to full speed
+                            let mut axis = if axis.is_finite() { axis } else { 0.0 };
+                            clamp(&mut axis, -1.0, 1.0);
+                            client.axis = axis;
+                        }
+                    }
+                }
+                (ClientMessage::Leave, Some(slot)) => {
+                    println!("player {} left", slot + 1);
+                    self.clients[slot] = None;
+                }
+                (message, None) => {
+                    let welcome = ServerMessage::Watching {
+                        field_w: FIELD_W,
+                        field_h: FIELD_H,
+                        rules: self.rules,
+                        tick_rate: self.tick_rate,
+                    };
+                    let before = self.spectators.count();
+                    self.spectators
+                        .receive(&self.socket, from, &message, &welcome);
+                    if self.spectators.count() != before {
+                        println!("{} watching", self.spectators.count());
+                    }
+                }
+                // inputs from someone who isn't playing
+                _ => (),
+            }
+        }
+    }
+
+    fn drop_silent_clients(&mut self) {
+        for (slot, client) in self.clients.iter_mut().enumerate() {
+            if client
+                .as_ref()
+                .is_some_and(|c| c.last_heard.elapsed() > TIMEOUT)
+            {
+                println!("player {} timed out", slot + 1);
+                *client = None;
+            }
+        }
+        if self.spectators.drop_silent() > 0 {
+            println!("{} watching", self.spectators.count());
+        }
+    }
+
+    fn step(&mut self, dt: f32) {
+        // the match only runs with both players there
+        if self.waiting() {
+            return;
+        }
+        if let Some(time_left) = &mut self.next_match {
+            *time_left -= dt;
+            if *time_left <= 0.0 {
+                println!("starting the next match");
+                self.new_match();
+            }
+            return;
+        }
+
+        let axis = |slot: usize| self.clients[slot].as_ref().map_or(0.0, |c| c.axis);
+        let input = Input {
+            player_1: axis(0),
+            player_2: axis(1),
+        };
+        self.recording.record(input);
+        for event in self.sim.step(input, dt) {
+            match event {
+                Event::GameWon(player) => println!(
+                    "player {} won a game, games {} - {}",
+                    player.index() + 1,
+                    self.sim.games[0],
+                    self.sim.games[1]
+                ),
+                Event::MatchWon(player) => {
+                    println!(
+                        "player {} won the match, games {} - {}, seed {}",
+                        player.index() + 1,
+                        self.sim.games[0],
+                        self.sim.games[1],
+                        self.sim.seed
+                    );
+                    self.save_recording();
+                    self.next_match = Some(NEXT_MATCH_DELAY);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn broadcast(&self) {
+        let snapshot = Snapshot::new(
+            self.tick,
+            &self.sim,
+            self.waiting(),
+            self.spectators.count(),
+        );
+        let message = ServerMessage::Snapshot(snapshot);
+        for client in self.clients.iter().flatten() {
+            self.send(client.address, &message);
+        }
+        self.spectators.send(&self.socket, &message);
+    }
+
+    fn run(&mut self) {
+        let tick_time = Duration::from_secs(1) / self.tick_rate;
+        let dt = 1.0 / self.tick_rate as f32;
+        let mut next_tick = Instant::now();
+        loop {
+            self.receive();
+            self.drop_silent_clients();
+            let open_slots = self.clients.iter().filter(|c| c.is_none()).count() as u8;
+            if let Some(announcer) = &mut self.announcer {
+                announcer.announcement.open_slots = open_slots;
+                announcer.update();
+            }
+            self.step(dt);
+            if self.tick % SNAPSHOT_INTERVAL == 0 {
+                self.broadcast();
+            }
+            self.tick = self.tick.wrapping_add(1);
+
+            // same idea as the game's fixed timestep, if we fall behind we just tick right away
+            next_tick += tick_time;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else {
+                next_tick = now;
+            }
+        }
+    }
+}
+
+fn main() {
+    let options = match Options::parse() {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Some(dir) = &options.record {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("could not create {}: {}", dir.display(), e);
+            process::exit(1);
+        }
+    }
+    let mut server = match Server::new(&options) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("could not listen on port {}: {}", options.port, e);
+            process::exit(1);
+        }
+    };
+    println!("pong-server listening on UDP port {}", options.port);
+    server.run();
+}
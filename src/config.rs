@@ -0,0 +1,51 @@
+//! Loading and saving our TOML config files in the user's config directory
+
+use directories::ProjectDirs;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Where the config files go, e.g. `~/.config/rusty_pong` on Linux
+pub fn config_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "samdal", "rusty_pong").map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+/// Where things the game writes by itself go, e.g. `~/.local/share/rusty_pong` on Linux
+pub fn data_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "samdal", "rusty_pong").map(|dirs| dirs.data_dir().to_path_buf())
+}
+
+/// Where recorded matches go
+pub fn replay_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("replays"))
+}
+
+/// Reads a config file. Returns `None` if it isn't there or doesn't make sense,
+/// in which case the caller should just use its defaults.
+pub fn load<T: DeserializeOwned>(file_name: &str) -> Option<T> {
+    let path = config_dir()?.join(file_name);
+    let text = fs::read_to_string(&path).ok()?;
+    match toml::from_str(&text) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            eprintln!("ignoring {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Whether there's a config file called `file_name`, readable or not
+pub fn exists(file_name: &str) -> bool {
+    config_dir().is_some_and(|dir| dir.join(file_name).exists())
+}
+
+/// Writes a config file, creating the config directory if needed
+pub fn save<T: Serialize>(file_name: &str, value: &T) -> io::Result<()> {
+    let dir = config_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    fs::create_dir_all(&dir)?;
+    let text = toml::to_string_pretty(value).map_err(io::Error::other)?;
+    fs::write(dir.join(file_name), text)
+}
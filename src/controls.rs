@@ -0,0 +1,365 @@
+//! Turns keys into things the game understands, so the keys can be
+//! rebound and saved to `controls.toml` in the config directory.
+
+use crate::config;
+use ggez::input::keyboard::{self, KeyCode};
+use ggez::Context;
+use std::collections::BTreeMap;
+
+const CONFIG_FILE: &str = "controls.toml";
+
+/// Something a key can be bound to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Control {
+    P1Up,
+    P1Down,
+    P2Up,
+    P2Down,
+    Pause,
+    /// Spectators switch between following the ball and seeing the whole field
+    Camera,
+    /// Shows the seed and other numbers, works everywhere
+    Debug,
+    /// Switches between a window and the whole screen, works everywhere
+    Fullscreen,
+    MenuUp,
+    MenuDown,
+    MenuLeft,
+    MenuRight,
+    Confirm,
+    Back,
+}
+
+impl Control {
+    pub const ALL: [Control; 14] = [
+        Control::P1Up,
+        Control::P1Down,
+        Control::P2Up,
+        Control::P2Down,
+        Control::Pause,
+        Control::Camera,
+        Control::Debug,
+        Control::Fullscreen,
+        Control::MenuUp,
+        Control::MenuDown,
+        Control::MenuLeft,
+        Control::MenuRight,
+        Control::Confirm,
+        Control::Back,
+    ];
+
+    /// The name used in the config file
+    fn config_name(self) -> &'static str {
+        match self {
+            Control::P1Up => "p1_up",
+            Control::P1Down => "p1_down",
+            Control::P2Up => "p2_up",
+            Control::P2Down => "p2_down",
+            Control::Pause => "pause",
+            Control::Camera => "camera",
+            Control::Debug => "debug",
+            Control::Fullscreen => "fullscreen",
+            Control::MenuUp => "menu_up",
+            Control::MenuDown => "menu_down",
+            Control::MenuLeft => "menu_left",
+            Control::MenuRight => "menu_right",
+            Control::Confirm => "confirm",
+            Control::Back => "back",
+        }
+    }
+
+    /// The name shown in menus
+    pub fn name(self) -> &'static str {
+        match self {
+            Control::P1Up => "Player 1 up",
+            Control::P1Down => "Player 1 down",
+            Control::P2Up => "Player 2 up",
+            Control::P2Down => "Player 2 down",
+            Control::Pause => "Pause",
+            Control::Camera => "Spectator camera",
+            Control::Debug => "Debug overlay",
+            Control::Fullscreen => "Fullscreen",
+            Control::MenuUp => "Menu up",
+            Control::MenuDown => "Menu down",
+            Control::MenuLeft => "Menu left",
+            Control::MenuRight => "Menu right",
+            Control::Confirm => "Confirm",
+            Control::Back => "Back",
+        }
+    }
+
+    /// Controls used while playing never clash with the ones used in menus,
+    /// so e.g. W can be both player 1 up and menu up
+    fn in_game(self) -> bool {
+        matches!(
+            self,
+            Control::P1Up
+                | Control::P1Down
+                | Control::P2Up
+                | Control::P2Down
+                | Control::Pause
+                | Control::Camera
+        )
+    }
+
+    fn default_keys(self) -> Vec<KeyCode> {
+        match self {
+            Control::P1Up => vec![KeyCode::W],
+            Control::P1Down => vec![KeyCode::S],
+            Control::P2Up => vec![KeyCode::Up],
+            Control::P2Down => vec![KeyCode::Down],
+            Control::Pause => vec![KeyCode::Escape],
+            Control::Camera => vec![KeyCode::V],
+            Control::Debug => vec![KeyCode::F3],
+            Control::Fullscreen => vec![KeyCode::F11],
+            Control::MenuUp => vec![KeyCode::Up, KeyCode::W],
+            Control::MenuDown => vec![KeyCode::Down, KeyCode::S],
+            Control::MenuLeft => vec![KeyCode::Left, KeyCode::A],
+            Control::MenuRight => vec![KeyCode::Right, KeyCode::D],
+            Control::Confirm => vec![KeyCode::Return, KeyCode::Space],
+            Control::Back => vec![KeyCode::Escape],
+        }
+    }
+}
+
+/// The keys that can go in the config file
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Key0,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Escape,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Insert,
+    KeyCode::Home,
+    KeyCode::Delete,
+    KeyCode::End,
+    KeyCode::PageDown,
+    KeyCode::PageUp,
+    KeyCode::Left,
+    KeyCode::Up,
+    KeyCode::Right,
+    KeyCode::Down,
+    KeyCode::Back,
+    KeyCode::Return,
+    KeyCode::Space,
+    KeyCode::Tab,
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+    KeyCode::NumpadEnter,
+    KeyCode::Add,
+    KeyCode::Subtract,
+    KeyCode::Multiply,
+    KeyCode::Divide,
+    KeyCode::Decimal,
+    KeyCode::Apostrophe,
+    KeyCode::Backslash,
+    KeyCode::Comma,
+    KeyCode::Equals,
+    KeyCode::Grave,
+    KeyCode::LBracket,
+    KeyCode::RBracket,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Semicolon,
+    KeyCode::Slash,
+    KeyCode::LShift,
+    KeyCode::RShift,
+    KeyCode::LControl,
+    KeyCode::RControl,
+    KeyCode::LAlt,
+    KeyCode::RAlt,
+];
+
+/// The name of a key, as written in the config file
+pub fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|&key| key_name(key).eq_ignore_ascii_case(name))
+}
+
+/// Which keys are bound to what
+#[derive(Clone, Debug)]
+pub struct Controls {
+    bindings: BTreeMap<Control, Vec<KeyCode>>,
+}
+
+impl Default for Controls {
+    fn default() -> Controls {
+        Controls {
+            bindings: Control::ALL
+                .iter()
+                .map(|&control| (control, control.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+impl Controls {
+    /// Loads the controls from the config file, anything missing or broken
+    /// in there gets the default keys
+    pub fn load() -> Controls {
+        let mut controls = Controls::default();
+        let file: BTreeMap<String, Vec<String>> = match config::load(CONFIG_FILE) {
+            Some(file) => file,
+            None => return controls,
+        };
+
+        for (name, key_names) in &file {
+            let control = match Control::ALL.iter().find(|c| c.config_name() == name) {
+                Some(&control) => control,
+                None => {
+                    eprintln!("{}: unknown control {:?}", CONFIG_FILE, name);
+                    continue;
+                }
+            };
+            let mut keys = Vec::new();
+            for key_name in key_names {
+                match key_from_name(key_name) {
+                    Some(key) => keys.push(key),
+                    None => eprintln!("{}: unknown key {:?}", CONFIG_FILE, key_name),
+                }
+            }
+            if !keys.is_empty() {
+                controls.bindings.insert(control, keys);
+            }
+        }
+
+        // a broken file could have one key doing two things at once
+        for &control in &Control::ALL {
+            for &key in controls.keys(control) {
+                if let Some(other) = controls.conflict(control, key) {
+                    eprintln!(
+                        "{}: {} is bound to both {} and {}, using the default controls",
+                        CONFIG_FILE,
+                        key_name(key),
+                        control.name(),
+                        other.name()
+                    );
+                    return Controls::default();
+                }
+            }
+        }
+        controls
+    }
+
+    /// Writes the controls to the config file
+    pub fn save(&self) {
+        let file: BTreeMap<&str, Vec<String>> = self
+            .bindings
+            .iter()
+            .map(|(control, keys)| {
+                let names = keys.iter().map(|&key| key_name(key)).collect();
+                (control.config_name(), names)
+            })
+            .collect();
+        if let Err(e) = config::save(CONFIG_FILE, &file) {
+            eprintln!("could not save {}: {}", CONFIG_FILE, e);
+        }
+    }
+
+    pub fn keys(&self, control: Control) -> &[KeyCode] {
+        self.bindings.get(&control).map_or(&[], |keys| keys)
+    }
+
+    /// Checks if `key` is bound to `control`
+    pub fn matches(&self, control: Control, key: KeyCode) -> bool {
+        self.keys(control).contains(&key)
+    }
+
+    /// Checks if any of the keys for `control` are held down
+    pub fn is_pressed(&self, ctx: &Context, control: Control) -> bool {
+        self.keys(control)
+            .iter()
+            .any(|&key| keyboard::is_key_pressed(ctx, key))
+    }
+
+    /// Reads a pair of controls as racket input, 1.0 is up
+    pub fn axis(&self, ctx: &Context, up: Control, down: Control) -> f32 {
+        let mut axis = 0.0;
+        if self.is_pressed(ctx, up) {
+            axis += 1.0;
+        }
+        if self.is_pressed(ctx, down) {
+            axis -= 1.0;
+        }
+        axis
+    }
+
+    /// Finds a different control that `key` is already bound to and that
+    /// could be used at the same time as `control`
+    pub fn conflict(&self, control: Control, key: KeyCode) -> Option<Control> {
+        self.bindings
+            .iter()
+            .find(|&(&other, keys)| {
+                other != control && other.in_game() == control.in_game() && keys.contains(&key)
+            })
+            .map(|(&other, _)| other)
+    }
+
+    /// Binds `control` to just `key`. Fails with the control that is
+    /// already using the key if it would clash.
+    pub fn rebind(&mut self, control: Control, key: KeyCode) -> Result<(), Control> {
+        if let Some(other) = self.conflict(control, key) {
+            return Err(other);
+        }
+        self.bindings.insert(control, vec![key]);
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+//! Finding games on the local network without typing in addresses.
+//! Anyone hosting shouts an announcement to the whole network every second,
+//! and the lobby screen listens for them.
+//!
+//! Only one program can listen on a port, so listeners take the first free one
+//! out of a small range and announcements go to all of them. That way several
+//! copies of the game on the same computer can all see each other.
+
+use crate::rules::Ruleset;
+use crate::wire::{Reader, Writer};
+use std::env;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Starts every announcement so stray traffic on the port gets ignored
+const MAGIC: &[u8; 4] = b"RPLA";
+const PROTOCOL_VERSION: u8 = 2;
+/// The first port listeners try
+pub const DISCOVERY_PORT: u16 = 7200;
+/// How many ports after `DISCOVERY_PORT` listeners may use
+const DISCOVERY_PORTS: u16 = 8;
+/// How often hosts announce themselves
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Games we haven't heard from in this long have probably gone away
+const FORGET_AFTER: Duration = Duration::from_secs(3);
+/// Longest name that fits in an announcement
+const MAX_NAME_LEN: usize = 32;
+const MAX_PACKET_SIZE: usize = 256;
+
+/// What sort of game is being announced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostKind {
+    /// Another copy of the game, joined with `--join`
+    Peer,
+    /// A `pong-server`, joined with `--connect`
+    Server,
+}
+
+/// What a host tells the network about its game
+#[derive(Clone, Debug, PartialEq)]
+pub struct Announcement {
+    /// Random for every host, so a game heard on two network interfaces only shows up once
+    pub id: u64,
+    pub kind: HostKind,
+    pub name: String,
+    /// The port the game itself is on
+    pub port: u16,
+    pub rules: Ruleset,
+    /// How many rackets are still free
+    pub open_slots: u8,
+}
+
+impl Announcement {
+    fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.bytes(MAGIC);
+        w.u8(PROTOCOL_VERSION);
+        w.u64(self.id);
+        w.u8(match self.kind {
+            HostKind::Peer => 0,
+            HostKind::Server => 1,
+        });
+        let name = truncate(&self.name, MAX_NAME_LEN);
+        w.u8(name.len() as u8);
+        w.bytes(name.as_bytes());
+        w.u16(self.port);
+        w.rules(&self.rules);
+        w.u8(self.open_slots);
+        w.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Announcement> {
+        let mut r = Reader::new(bytes);
+        if r.bytes(MAGIC.len())? != MAGIC || r.u8()? != PROTOCOL_VERSION {
+            return None;
+        }
+        let id = r.u64()?;
+        let kind = match r.u8()? {
+            0 => HostKind::Peer,
+            1 => HostKind::Server,
+            _ => return None,
+        };
+        let name_len = r.u8()? as usize;
+        let name = String::from_utf8_lossy(r.bytes(name_len)?).into_owned();
+        Some(Announcement {
+            id,
+            kind,
+            name,
+            port: r.u16()?,
+            rules: r.rules()?,
+            open_slots: r.u8()?,
+        })
+    }
+}
+
+/// Cuts `text` down to at most `max` bytes without splitting a character
+fn truncate(text: &str, max: usize) -> &str {
+    let mut end = text.len().min(max);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// The name of this computer, to tell games in the lobby apart
+pub fn host_name() -> String {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "pong".to_string())
+}
+
+/// Tells the network about a game every so often
+pub struct Announcer {
+    socket: UdpSocket,
+    pub announcement: Announcement,
+    last_sent: Option<Instant>,
+}
+
+impl Announcer {
+    pub fn new(announcement: Announcement) -> io::Result<Announcer> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_broadcast(true)?;
+        Ok(Announcer {
+            socket,
+            announcement,
+            last_sent: None,
+        })
+    }
+
+    /// Sends the announcement if it's been long enough since the last one
+    pub fn update(&mut self) {
+        if self
+            .last_sent
+            .is_some_and(|sent| sent.elapsed() < ANNOUNCE_INTERVAL)
+        {
+            return;
+        }
+        self.last_sent = Some(Instant::now());
+
+        let packet = self.announcement.encode();
+        for port in DISCOVERY_PORT..DISCOVERY_PORT + DISCOVERY_PORTS {
+            // broadcasts don't always come back to the same computer, so tell it directly too.
+            // Either can fail, e.g. with no network at all, which just means nobody hears it.
+            let _ = self.socket.send_to(&packet, (Ipv4Addr::BROADCAST, port));
+            let _ = self.socket.send_to(&packet, (Ipv4Addr::LOCALHOST, port));
+        }
+    }
+}
+
+/// A game heard on the network
+#[derive(Clone, Debug)]
+pub struct FoundGame {
+    /// Where to join it
+    pub address: SocketAddr,
+    pub announcement: Announcement,
+    last_heard: Instant,
+}
+
+/// Listens for announcements and keeps a list of the games out there
+pub struct Listener {
+    socket: UdpSocket,
+    games: Vec<FoundGame>,
+}
+
+impl Listener {
+    /// Starts listening on the first free discovery port
+    pub fn new() -> io::Result<Listener> {
+        let mut last_error = None;
+        for port in DISCOVERY_PORT..DISCOVERY_PORT + DISCOVERY_PORTS {
+            match UdpSocket::bind(("0.0.0.0", port)) {
+                Ok(socket) => {
+                    socket.set_nonblocking(true)?;
+                    return Ok(Listener {
+                        socket,
+                        games: Vec::new(),
+                    });
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::Error::other("no discovery ports")))
+    }
+
+    /// The games heard from lately, in the order they were first found
+    pub fn games(&self) -> &[FoundGame] {
+        &self.games
+    }
+
+    /// Reads the announcements that have arrived and forgets games that went quiet
+    pub fn update(&mut self) {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let (size, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            let announcement = match Announcement::decode(&buffer[..size]) {
+                Some(announcement) => announcement,
+                None => continue,
+            };
+            let address = SocketAddr::new(from.ip(), announcement.port);
+            match self
+                .games
+                .iter_mut()
+                .find(|game| game.announcement.id == announcement.id)
+            {
+                Some(game) => {
+                    game.announcement = announcement;
+                    game.last_heard = Instant::now();
+                }
+                None => self.games.push(FoundGame {
+                    address,
+                    announcement,
+                    last_heard: Instant::now(),
+                }),
+            }
+        }
+        self.games
+            .retain(|game| game.last_heard.elapsed() < FORGET_AFTER);
+    }
+}
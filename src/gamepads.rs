@@ -0,0 +1,101 @@
+//! Gamepad support through ggez's gilrs events.
+//! A gamepad joins the first free side the first time one of its buttons is
+//! pressed, and frees that side up again when it gets unplugged.
+
+use crate::controls::Control;
+use ggez::event::{Axis, Button, GamepadId};
+use ggez::input::gamepad;
+use ggez::Context;
+
+/// Stick movement smaller than this is ignored so a worn out stick doesn't drift the racket
+const STICK_DEAD_ZONE: f32 = 0.15;
+
+/// What a gamepad button does, the same things keys can be bound to.
+/// Start pauses the game and picks the first entry in menus, which is always resume.
+pub fn button_controls(button: Button) -> &'static [Control] {
+    match button {
+        Button::DPadUp => &[Control::MenuUp],
+        Button::DPadDown => &[Control::MenuDown],
+        Button::DPadLeft => &[Control::MenuLeft],
+        Button::DPadRight => &[Control::MenuRight],
+        Button::South => &[Control::Confirm],
+        Button::East => &[Control::Back],
+        Button::North => &[Control::Camera],
+        Button::Start => &[Control::Pause, Control::Confirm],
+        _ => &[],
+    }
+}
+
+/// Which gamepad is playing which side
+#[derive(Debug, Default)]
+pub struct Gamepads {
+    /// The gamepad on each side, player 1 first
+    sides: [Option<GamepadId>; 2],
+}
+
+impl Gamepads {
+    /// The side a gamepad is playing on
+    pub fn side_of(&self, id: GamepadId) -> Option<usize> {
+        self.sides.iter().position(|&side| side == Some(id))
+    }
+
+    pub fn has_gamepad(&self, side: usize) -> bool {
+        self.sides[side].is_some()
+    }
+
+    /// Puts a new gamepad on the first free side.
+    /// Returns the side if it just joined, `None` if it was already playing or there was no room.
+    pub fn join(&mut self, id: GamepadId) -> Option<usize> {
+        if self.side_of(id).is_some() {
+            return None;
+        }
+        let side = self.sides.iter().position(Option::is_none)?;
+        self.sides[side] = Some(id);
+        Some(side)
+    }
+
+    /// Moves a gamepad over to the other side, swapping with the gamepad there if there is one.
+    /// Returns the side it ended up on.
+    pub fn switch_side(&mut self, id: GamepadId) -> Option<usize> {
+        self.side_of(id)?;
+        self.sides.swap(0, 1);
+        self.side_of(id)
+    }
+
+    /// Forgets gamepads that have been unplugged, returns the sides they were playing
+    pub fn drop_disconnected(&mut self, ctx: &Context) -> Vec<usize> {
+        let mut dropped = Vec::new();
+        for (side, slot) in self.sides.iter_mut().enumerate() {
+            if let Some(id) = *slot {
+                if !gamepad::gamepad(ctx, id).is_connected() {
+                    *slot = None;
+                    dropped.push(side);
+                }
+            }
+        }
+        dropped
+    }
+
+    /// Racket input from the gamepad on a side, 1.0 is up.
+    /// The further the stick is pushed the faster the racket goes, the d-pad is always full speed.
+    pub fn axis(&self, ctx: &Context, side: usize) -> f32 {
+        let id = match self.sides[side] {
+            Some(id) => id,
+            None => return 0.0,
+        };
+        let pad = gamepad::gamepad(ctx, id);
+        if pad.is_pressed(Button::DPadUp) {
+            return 1.0;
+        }
+        if pad.is_pressed(Button::DPadDown) {
+            return -1.0;
+        }
+
+        let stick = pad.value(Axis::LeftStickY);
+        if stick.abs() < STICK_DEAD_ZONE {
+            return 0.0;
+        }
+        // rescale so the racket starts moving slowly right at the edge of the dead zone
+        stick.signum() * (stick.abs() - STICK_DEAD_ZONE) / (1.0 - STICK_DEAD_ZONE)
+    }
+}
@@ -0,0 +1,11 @@
+//! The parts of the game that don't need a window, shared by the game
+//! itself and the dedicated `pong-server`.
+
+pub mod discovery;
+pub mod opponent;
+pub mod protocol;
+pub mod replay;
+pub mod rules;
+pub mod simulation;
+pub mod spectators;
+pub mod wire;
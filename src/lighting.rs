@@ -0,0 +1,455 @@
+//! The lights on the field and the shadows the rackets cast.
+//! The shaders cast rays out from every light to find what's in the way, all
+//! lights at once with a row of the occlusion map each, then draw the shadows
+//! and the light itself from how far each ray got.
+//! Some graphics cards can't compile the shaders, the game is drawn unlit then.
+
+use crate::shaders;
+use gfx::{self, *};
+use ggez::conf;
+use ggez::graphics::{self, BlendMode, Canvas, DrawParam, Drawable, Shader};
+use ggez::nalgebra as na;
+use ggez::{Context, GameError, GameResult};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// How good the light and shadows look, and how much they cost
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    /// No shaders at all, everything is drawn fully lit
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    pub const ALL: [Quality; 4] = [Quality::Off, Quality::Low, Quality::Medium, Quality::High];
+
+    /// How many directions the light checks for shadows, the width of the occlusion map
+    pub fn rays(self) -> u16 {
+        match self {
+            Quality::Off => 0,
+            Quality::Low => 256,
+            Quality::Medium => 620,
+            Quality::High => 1240,
+        }
+    }
+
+    /// How many steps each ray takes looking for something in the way
+    fn steps(self) -> u32 {
+        match self {
+            Quality::Off => 0,
+            Quality::Low => 256,
+            Quality::Medium => 1024,
+            Quality::High => 2048,
+        }
+    }
+
+    /// How big the shadow and light maps are next to the window
+    fn resolution(self) -> f32 {
+        match self {
+            Quality::Low => 0.5,
+            _ => 1.0,
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Quality::Off => "Off",
+            Quality::Low => "Low",
+            Quality::Medium => "Medium",
+            Quality::High => "High",
+        })
+    }
+}
+
+impl FromStr for Quality {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Quality, ()> {
+        Quality::ALL
+            .iter()
+            .copied()
+            .find(|quality| quality.to_string().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+// I have noe clue what the hell the below code does
+// I just yanked it from the examples lol
+// https://github.com/ggez/ggez/blob/master/examples/shadows.rs
+
+/// The most lights the shaders can take in one frame
+pub const MAX_LIGHTS: usize = 16;
+/// The color cast things take when not illuminated
+const AMBIENT_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// A `vec4` for every light the shaders can take. gfx only knows about single
+/// values and matrices, so it gets told this is a single `vec4` at the start of the array.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(transparent)]
+pub struct LightArray([[f32; 4]; MAX_LIGHTS]);
+
+impl gfx::shade::Formatted for LightArray {
+    fn get_format() -> gfx::shade::ConstFormat {
+        (
+            gfx::shade::core::BaseType::F32,
+            gfx::shade::core::ContainerType::Vector(4),
+        )
+    }
+}
+
+gfx_defines! {
+    /// Constants used by the shaders to calculate stuff, about all the lights at once
+    #[repr(C)]
+    constant Lights {
+        colors: LightArray = "u_Colors[0]",
+        /// Where each light is between 0 and 1 from the lower left corner,
+        /// then its strength and glow
+        lights: LightArray = "u_Lights[0]",
+        shadow_color: [f32; 4] = "u_ShadowColor",
+        screen_size: [f32; 2] = "u_ScreenSize",
+        /// How many of the lights are in use
+        count: i32 = "u_Count",
+    }
+}
+
+impl Lights {
+    /// No lights on a `screen_size` big canvas
+    fn new(screen_size: [f32; 2]) -> Lights {
+        Lights {
+            colors: LightArray([[0.0; 4]; MAX_LIGHTS]),
+            lights: LightArray([[0.0; 4]; MAX_LIGHTS]),
+            shadow_color: AMBIENT_COLOR,
+            screen_size,
+            count: 0,
+        }
+    }
+}
+
+/// A light to draw
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    /// Between 0 and 1 from the lower left corner of the window, like the shaders want it
+    pub pos: [f32; 2],
+    pub color: [f32; 4],
+    /// How far it shines
+    pub strength: f32,
+    /// Added on to the strength, to make it pulse
+    pub glow: f32,
+}
+
+/// Shader for casting the rays. Every row of the occlusion map is one light
+/// and every pixel in it one direction out from that light, which gets how far
+/// the ray in that direction got before hitting something. `STEPS` is how many
+/// steps a ray takes, it gets defined from the quality preset.
+const OCCLUSIONS_SHADER_FILE: &str = "/occlusions_150.glslf";
+const OCCLUSIONS_SHADER_SOURCE: &str = include_str!("../resources/occlusions_150.glslf");
+
+const VERTEX_SHADER_SOURCE: &[u8] = include_bytes!("../resources/basic_150.glslv");
+
+/// Shader for drawing shadows based on the occlusion map. For every light it
+/// takes current fragment coordinates and converts them to polar coordinates
+/// centered around the light, using the angle to sample from the light's row of
+/// the occlusion map. If the distance from the light is greater than the distance
+/// of the closest reported shadow, that light doesn't reach it, else it calculates
+/// some shadow based on the distance from the light and its strength and glow.
+/// A spot is as dark as the shadow of the light that lights it best.
+const SHADOWS_SHADER_FILE: &str = "/shadows_150.glslf";
+const SHADOWS_SHADER_SOURCE: &str = include_str!("../resources/shadows_150.glslf");
+
+/// Shader for drawing lights based on the occlusion map. It works out how much
+/// of every light reaches each spot like the shadows shader does, and adds them
+/// all up in their colors.
+const LIGHTS_SHADER_FILE: &str = "/lights_150.glslf";
+const LIGHTS_SHADER_SOURCE: &str = include_str!("../resources/lights_150.glslf");
+
+/// The fragment shader files in the resources directory, to watch for changes
+pub const SHADER_FILES: [&str; 3] = [
+    OCCLUSIONS_SHADER_FILE,
+    SHADOWS_SHADER_FILE,
+    LIGHTS_SHADER_FILE,
+];
+/// The copies of them built into the game
+const BUILT_IN_SHADERS: [&str; 3] = [
+    OCCLUSIONS_SHADER_SOURCE,
+    SHADOWS_SHADER_SOURCE,
+    LIGHTS_SHADER_SOURCE,
+];
+
+/// Defines the sizes the shader sources leave open, right after the `#version`
+/// line since nothing but comments may come before it
+fn shader_source(source: &str, steps: u32) -> String {
+    let defines = format!(
+        "#define MAX_LIGHTS {}\n#define STEPS {}\n",
+        MAX_LIGHTS, steps
+    );
+    let version_end = source.find("#version").map(|start| {
+        source[start..]
+            .find('\n')
+            .map_or(source.len(), |end| start + end)
+    });
+    match version_end {
+        Some(end) => format!("{}\n{}{}", &source[..end], defines, &source[end..]),
+        None => defines + source,
+    }
+}
+
+/// A light somewhere on the field that fades out, like the flash when someone scores
+#[derive(Clone, Debug)]
+pub struct Flash {
+    pub pos: na::Point2<f32>,
+    pub color: [f32; 4],
+    pub strength: f32,
+    /// Seconds it takes to fade out
+    duration: f32,
+    age: f32,
+}
+
+impl Flash {
+    pub fn new(pos: na::Point2<f32>, color: [f32; 4], strength: f32, duration: f32) -> Flash {
+        Flash {
+            pos,
+            color,
+            strength,
+            duration,
+            age: 0.0,
+        }
+    }
+
+    /// Ages the flash by `dt` seconds
+    pub fn update(&mut self, dt: f32) {
+        self.age += dt;
+    }
+
+    pub fn faded(&self) -> bool {
+        self.age >= self.duration
+    }
+
+    /// How strong it is now, it dims from `strength` to nothing
+    pub fn strength_now(&self) -> f32 {
+        self.strength * (1.0 - self.age / self.duration).max(0.0)
+    }
+}
+
+/// The canvases and shaders that light the field
+pub struct Lighting {
+    quality: Quality,
+    /// The size of the window-sized canvases, smaller than the window on low quality
+    size: (f32, f32),
+    /// Everything that casts shadows gets drawn here first
+    foreground: Canvas,
+    /// A row for every light, all worked out in one go
+    occlusions: Canvas,
+    shadows: Canvas,
+    lights: Canvas,
+    shaders: Shaders,
+}
+
+/// The three light shaders, compiled for a quality preset
+struct Shaders {
+    occlusions: Shader<Lights>,
+    shadows: Shader<Lights>,
+    lights: Shader<Lights>,
+}
+
+impl Shaders {
+    /// Compiles the occlusions, shadows and lights shader `sources`, with rays taking `steps` steps
+    fn compile<S: AsRef<str>>(
+        ctx: &mut Context,
+        steps: u32,
+        sources: &[S; 3],
+    ) -> GameResult<Shaders> {
+        // the real uniforms get sent before every draw
+        let lights = Lights::new([1.0, 1.0]);
+        let mut compile = |source: &S| {
+            Shader::from_u8(
+                ctx,
+                VERTEX_SHADER_SOURCE,
+                shader_source(source.as_ref(), steps).as_bytes(),
+                lights,
+                "Lights",
+                None,
+            )
+        };
+        Ok(Shaders {
+            occlusions: compile(&sources[0])?,
+            shadows: compile(&sources[1])?,
+            lights: compile(&sources[2])?,
+        })
+    }
+}
+
+impl Lighting {
+    /// Sets up lighting for a `window_w` by `window_h` window, with `rays` rays
+    /// and room for `max_lights` lights. Fails if the graphics card can't compile the shaders.
+    pub fn new(
+        ctx: &mut Context,
+        quality: Quality,
+        rays: u16,
+        max_lights: usize,
+        window_w: f32,
+        window_h: f32,
+    ) -> GameResult<Lighting> {
+        let steps = quality.steps();
+        let sources = [
+            shaders::source(ctx, OCCLUSIONS_SHADER_FILE, OCCLUSIONS_SHADER_SOURCE),
+            shaders::source(ctx, SHADOWS_SHADER_FILE, SHADOWS_SHADER_SOURCE),
+            shaders::source(ctx, LIGHTS_SHADER_FILE, LIGHTS_SHADER_SOURCE),
+        ];
+        let shaders = match Shaders::compile(ctx, steps, &sources) {
+            Ok(shaders) => shaders,
+            Err(e) if sources != BUILT_IN_SHADERS => {
+                eprintln!(
+                    "the light shaders in resources don't compile, using the built in ones: {}",
+                    e
+                );
+                Shaders::compile(ctx, steps, &BUILT_IN_SHADERS)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        let (width, height) = (
+            window_w * quality.resolution(),
+            window_h * quality.resolution(),
+        );
+        let rows = max_lights.clamp(1, MAX_LIGHTS) as u16;
+        Ok(Lighting {
+            quality,
+            size: (width, height),
+            foreground: window_canvas(ctx, width, height, None)?,
+            occlusions: Canvas::new(ctx, rays, rows, conf::NumSamples::One)?,
+            // The shadow map will be drawn on top using the multiply blend mode
+            shadows: window_canvas(ctx, width, height, Some(BlendMode::Multiply))?,
+            // The light map will be drawn on top using the add blend mode
+            lights: window_canvas(ctx, width, height, Some(BlendMode::Add))?,
+            shaders,
+        })
+    }
+
+    /// Compiles the shader files in resources again and switches to them.
+    /// If any of them doesn't compile the old ones are kept and the error returned.
+    pub fn reload_shaders(&mut self, ctx: &mut Context) -> GameResult {
+        let mut read = |file: &str| {
+            shaders::read(ctx, file)
+                .map_err(|e| GameError::ResourceLoadError(format!("{}: {}", file, e)))
+        };
+        let sources = [
+            read(OCCLUSIONS_SHADER_FILE)?,
+            read(SHADOWS_SHADER_FILE)?,
+            read(LIGHTS_SHADER_FILE)?,
+        ];
+        self.shaders = Shaders::compile(ctx, self.quality.steps(), &sources)?;
+        Ok(())
+    }
+
+    /// Remakes the canvases for a window that changed size
+    pub fn resize(&mut self, ctx: &mut Context, window_w: f32, window_h: f32) -> GameResult {
+        let (width, height) = (
+            window_w * self.quality.resolution(),
+            window_h * self.quality.resolution(),
+        );
+        self.size = (width, height);
+        self.foreground = window_canvas(ctx, width, height, None)?;
+        self.shadows = window_canvas(ctx, width, height, Some(BlendMode::Multiply))?;
+        self.lights = window_canvas(ctx, width, height, Some(BlendMode::Add))?;
+        Ok(())
+    }
+
+    /// Starts a frame. Everything drawn from here until `render` casts shadows.
+    pub fn begin(&self, ctx: &mut Context) {
+        graphics::set_canvas(ctx, Some(&self.foreground));
+        graphics::clear(ctx, graphics::Color::new(0.0, 0.0, 0.0, 0.0));
+    }
+
+    //se example and official documentation
+    /// Works out the shadow and light maps of `lights` from what was drawn since `begin`.
+    /// Lights past the room there is for are left out.
+    pub fn render(&mut self, ctx: &mut Context, lights: &[Light]) -> GameResult {
+        let (width, height) = self.size;
+        let rays = f32::from(self.occlusions.image().width());
+        let rows = usize::from(self.occlusions.image().height());
+
+        // the shaders work in pixels of the canvases they draw on
+        let mut uniforms = Lights::new([width, height]);
+        uniforms.count = lights.len().min(rows) as i32;
+        for (i, light) in lights.iter().take(rows).enumerate() {
+            uniforms.colors.0[i] = light.color;
+            uniforms.lights.0[i] = [light.pos[0], light.pos[1], light.strength, light.glow];
+        }
+
+        // Now we want to run the occlusions shader to calculate the 1D shadow
+        // distances of every light into its row of the `occlusions` canvas.
+        graphics::set_canvas(ctx, Some(&self.occlusions));
+        {
+            let _shader_lock = graphics::use_shader(ctx, &self.shaders.occlusions);
+
+            self.shaders.occlusions.send(ctx, uniforms)?;
+            let param = cover_window(ctx, width, height);
+            graphics::draw(ctx, &self.foreground, param)?;
+        }
+
+        // Now we render our shadow map and light map into their respective
+        // canvases based on the occlusion map. These will then be drawn onto
+        // the final render target using appropriate blending modes.
+        graphics::set_canvas(ctx, Some(&self.shadows));
+        {
+            let _shader_lock = graphics::use_shader(ctx, &self.shaders.shadows);
+
+            let param = cover_window(ctx, rays, rows as f32);
+            self.shaders.shadows.send(ctx, uniforms)?;
+            graphics::draw(ctx, &self.occlusions, param)?;
+        }
+        graphics::set_canvas(ctx, Some(&self.lights));
+        {
+            let _shader_lock = graphics::use_shader(ctx, &self.shaders.lights);
+
+            let param = cover_window(ctx, rays, rows as f32);
+            self.shaders.lights.send(ctx, uniforms)?;
+            graphics::draw(ctx, &self.occlusions, param)?;
+        }
+        graphics::set_canvas(ctx, None);
+        Ok(())
+    }
+
+    /// The light map of the last `render` and how big it is, for effects that
+    /// want to know where the light is
+    pub fn light_map(&self) -> (&Canvas, f32, f32) {
+        (&self.lights, self.size.0, self.size.1)
+    }
+
+    /// Draws the shadows, then what casts them and then the light over
+    /// whatever is on the screen already
+    pub fn draw(&self, ctx: &mut Context) -> GameResult {
+        let (width, height) = self.size;
+        let param = cover_window(ctx, width, height);
+        graphics::draw(ctx, &self.shadows, param)?;
+        graphics::draw(ctx, &self.foreground, param)?;
+        graphics::draw(ctx, &self.lights, param)
+    }
+}
+
+/// Stretches something `width` by `height` big over the whole window
+pub fn cover_window(ctx: &Context, width: f32, height: f32) -> DrawParam {
+    let screen = graphics::screen_coordinates(ctx);
+    DrawParam::new()
+        .dest(na::Point2::new(screen.x, screen.y))
+        .scale(na::Vector2::new(screen.w / width, screen.h / height))
+}
+
+/// A canvas covering a `width` by `height` window, drawn with `blend_mode`
+pub fn window_canvas(
+    ctx: &mut Context,
+    width: f32,
+    height: f32,
+    blend_mode: Option<BlendMode>,
+) -> GameResult<Canvas> {
+    let mut canvas = Canvas::new(ctx, width as u16, height as u16, conf::NumSamples::One)?;
+    canvas.set_blend_mode(blend_mode);
+    Ok(canvas)
+}
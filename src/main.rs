@@ -1,248 +1,245 @@
+mod args;
+mod config;
+mod controls;
+mod gamepads;
+mod lighting;
+mod netplay;
+mod particles;
+mod playback;
+mod postprocess;
+mod remote;
+mod results;
+mod scenes;
+mod settings;
+mod shaders;
+
+use args::{Args, NetMode};
 use cgmath::{Point2, Vector2};
-use gfx::{self, *};
+use controls::{key_name, Control, Controls};
+use gamepads::Gamepads;
 use ggez::conf;
-use ggez::event;
-use ggez::graphics::{self, BlendMode, Canvas, DrawParam, Drawable, Shader};
-use ggez::input::keyboard::{self, KeyCode};
+use ggez::event::{self, Button, GamepadId};
+use ggez::graphics::{self, DrawParam};
+use ggez::input::keyboard::{KeyCode, KeyMods};
+use ggez::input::mouse;
 use ggez::nalgebra as na;
 use ggez::timer;
 use ggez::{Context, GameResult};
-use rand::{self, thread_rng, Rng};
+use lighting::{Flash, Light, Lighting, Quality};
+use netplay::NetSession;
+use particles::Particles;
+use playback::Playback;
+use pong::discovery::{self, Announcement, Announcer, HostKind, Listener};
+use pong::opponent::{self, ControllerKind, Level, Opponent};
+use pong::replay::Replay;
+use pong::rules::{Ruleset, ServeRule};
+use pong::simulation::{
+    check_tick_rate, clamp, Event, Input, Player, Simulation, BALL_SIZE, BALL_SIZE_HALF, FIELD_H,
+    FIELD_W, RACKET_HEIGHT, RACKET_HEIGHT_HALF, RACKET_WIDTH, RACKET_WIDTH_HALF, TICK_RATE,
+};
+use postprocess::PostProcess;
+use remote::ServerConnection;
+use scenes::{Action, Scene, SceneStack};
+use settings::{GraphicsSettings, Settings};
 use std::env;
+use std::f32::consts::PI;
+use std::fs;
 use std::path;
 
-///Padding between the rackets and the edge of the screen
-const PADDING: f32 = 10.0;
 ///width of the middle line
 const MIDDLE_LINE_W: f32 = 1.0;
-///Height of a racket
-const RACKET_HEIGHT: f32 = 100.0;
-///Widthe of a racket
-const RACKET_WIDTH: f32 = 10.0;
-///Racket height devided by two
-const RACKET_HEIGHT_HALF: f32 = RACKET_HEIGHT * 0.5;
-///Racket width devided by two
-const RACKET_WIDTH_HALF: f32 = RACKET_WIDTH * 0.5;
-///Diameter of the ball
-const BALL_SIZE: f32 = 10.0;
-///Radius of the ball
-const BALL_SIZE_HALF: f32 = BALL_SIZE * 0.5;
-///speed of the player racket
-const PLAYER_SPEED: f32 = 600.0;
-///starting speed of the ball
-const BALL_SPEED: f32 = 270.0;
-
-/// The color cast things take when not illuminated
-const AMBIENT_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-/// The number of rays to cast to. Increasing this number will result in better
-/// quality shadows. If you increase too much you might hit some GPU shader
-/// hardware limits.
-const LIGHT_RAY_COUNT: u16 = 620;
+///top speed of a mouse controlled racket as a fraction of PLAYER_SPEED, picked in Options
+const MOUSE_SPEEDS: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+///seconds a notice stays at the bottom of the screen
+const NOTICE_TIME: f32 = 3.0;
+///seconds skipped by one press of left or right while watching a replay
+const REPLAY_SKIP: f32 = 5.0;
+///the color of the ball and its light before anyone has hit it
+const NEUTRAL_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+
 /// The strength of the light - how far it shines
 const LIGHT_STRENGTH: f32 = 0.0005;
+/// The strength of the light hanging over the middle of the field in the spectator overview
+const OVERVIEW_LIGHT_STRENGTH: f32 = 0.05;
 /// The factor at which the light glows - just for fun
 const LIGHT_GLOW_FACTOR: f32 = 0.000005;
 /// The rate at which the glow effect oscillates
 const LIGHT_GLOW_RATE: f32 = 5.0;
+/// The strength of the glow in front of each racket
+const RACKET_LIGHT_STRENGTH: f32 = 0.00005;
+/// How far in front of a racket its glow sits, inside the racket it would be in its own shadow
+const RACKET_LIGHT_OFFSET: f32 = 8.0;
+/// The strength of the flash where the ball goes out, and the seconds it takes to fade
+const GOAL_FLASH_STRENGTH: f32 = 0.004;
+const GOAL_FLASH_TIME: f32 = 0.6;
+/// The strength of the flash where the ball hits a racket, and the seconds it takes to fade
+const HIT_FLASH_STRENGTH: f32 = 0.0003;
+const HIT_FLASH_TIME: f32 = 0.25;
+/// The strength of the light of a spark that was just emitted, it fades with the spark
+const PARTICLE_LIGHT_STRENGTH: f32 = 0.00003;
+///the color of the dust that comes off the walls
+const DUST_COLOR: [f32; 4] = [0.8, 0.8, 0.8, 0.6];
 
-// I have noe clue what the hell the below code does
-// I just yanked it from the examples lol
-// https://github.com/ggez/ggez/blob/master/examples/shadows.rs
+/// Draws a line of text centered horizontally on the screen
+fn draw_centered_text(
+    ctx: &mut Context,
+    text: &str,
+    size: f32,
+    y: f32,
+    color: graphics::Color,
+) -> GameResult {
+    let text = graphics::Text::new(
+        graphics::TextFragment::new(text)
+            .scale(graphics::Scale::uniform(size))
+            .color(color),
+    );
+    let (text_w, text_h) = text.dimensions(ctx);
+    let screen = graphics::screen_coordinates(ctx);
+    graphics::draw(
+        ctx,
+        &text,
+        DrawParam::new().dest(Point2::new(
+            screen.x + (screen.w - text_w as f32) * 0.5,
+            y - text_h as f32 * 0.5,
+        )),
+    )
+}
 
-gfx_defines! {
-    /// Constants used by the shaders to calculate stuff
-    constant Light {
-        light_color: [f32; 4] = "u_LightColor",
-        shadow_color: [f32; 4] = "u_ShadowColor",
-        pos: [f32; 2] = "u_Pos",
-        screen_size: [f32; 2] = "u_ScreenSize",
-        glow: f32 = "u_Glow",
-        strength: f32 = "u_Strength",
+/// The part of the field's coordinates a `window_w` by `window_h` window shows,
+/// so the whole field fits in the middle and black bars fill the rest
+fn letterbox(window_w: f32, window_h: f32, field_w: f32, field_h: f32) -> graphics::Rect {
+    // a minimized window has no size to fit anything into
+    if window_w < 1.0 || window_h < 1.0 {
+        return graphics::Rect::new(0.0, 0.0, field_w, field_h);
     }
+    let scale = (window_w / field_w).min(window_h / field_h);
+    let (w, h) = (window_w / scale, window_h / scale);
+    graphics::Rect::new((field_w - w) * 0.5, (field_h - h) * 0.5, w, h)
 }
 
-const OCCLUSIONS_SHADER_SOURCE: &[u8] = b"#version 150 core
-uniform sampler2D t_Texture;
-in vec2 v_Uv;
-out vec4 Target0;
-layout (std140) uniform Light {
-    vec4 u_LightColor;
-    vec4 u_ShadowColor;
-    vec2 u_Pos;
-    vec2 u_ScreenSize;
-    float u_Glow;
-    float u_Strength;
-};
-void main() {
-    float dist = 1.0;
-    float theta = radians(v_Uv.x * 360.0);
-    vec2 dir = vec2(cos(theta), sin(theta));
-    for(int i = 0; i < 1024; i++) {
-        float fi = i;
-        float r = fi / 1024.0;
-        vec2 rel = r * dir;
-        vec2 p = clamp(u_Pos+rel, 0.0, 1.0);
-        if (texture(t_Texture, p).a > 0.8) {
-            dist = distance(u_Pos, p) * 0.5;
-            break;
-        }
-    }
-    float others = dist == 1.0 ? 0.0 : dist;
-    Target0 = vec4(dist, others, others, 1.0);
-}
-";
-
-const VERTEX_SHADER_SOURCE: &[u8] = include_bytes!("../resources/basic_150.glslv");
-
-/// Shader for drawing shadows based on a 1D shadow map. It takes current
-/// fragment coordinates and converts them to polar coordinates centered
-/// around the light source, using the angle to sample from the 1D shadow map.
-/// If the distance from the light source is greater than the distance of the
-/// closest reported shadow, then the output is the shadow color, else it calculates some
-/// shadow based on the distance from light source based on strength and glow
-/// uniform parameters.
-const SHADOWS_SHADER_SOURCE: &[u8] = b"#version 150 core
-uniform sampler2D t_Texture;
-in vec2 v_Uv;
-out vec4 Target0;
-layout (std140) uniform Light {
-    vec4 u_LightColor;
-    vec4 u_ShadowColor;
-    vec2 u_Pos;
-    vec2 u_ScreenSize;
-    float u_Glow;
-    float u_Strength;
-};
-void main() {
-    vec2 coord = gl_FragCoord.xy / u_ScreenSize;
-    vec2 rel = coord - u_Pos;
-    float theta = atan(rel.y, rel.x);
-    float ox = degrees(theta) / 360.0;
-    if (ox < 0) {
-        ox += 1.0;
-    }
-    float r = length(rel);
-    float occl = texture(t_Texture, vec2(ox, 0.5)).r * 2.0;
-    float intensity = 1.0;
-    if (r < occl) {
-        vec2 g = u_ScreenSize / u_ScreenSize.y;
-        float p = u_Strength + u_Glow;
-        float d = distance(g * coord, g * u_Pos);
-        intensity = 1.0 - clamp(p/(d*d), 0.0, 1.0);
-    }
-    Target0 = mix(vec4(1.0, 1.0, 1.0, 1.0), vec4(u_ShadowColor.rgb, 1.0), intensity);
-}
-";
-
-/// Shader for drawing lights based on a 1D shadow map. It takes current
-/// fragment coordinates and converts them to polar coordinates centered
-/// around the light source, using the angle to sample from the 1D shadow map.
-/// If the distance from the light source is greater than the distance of the
-/// closest reported shadow, then the output is black, else it calculates some
-/// light based on the distance from light source based on strength and glow
-/// uniform parameters. It is meant to be used additively for drawing multiple
-/// lights.
-const LIGHTS_SHADER_SOURCE: &[u8] = b"#version 150 core
-uniform sampler2D t_Texture;
-in vec2 v_Uv;
-out vec4 Target0;
-layout (std140) uniform Light {
-    vec4 u_LightColor;
-    vec4 u_ShadowColor;
-    vec2 u_Pos;
-    vec2 u_ScreenSize;
-    float u_Glow;
-    float u_Strength;
-};
-void main() {
-    vec2 coord = gl_FragCoord.xy / u_ScreenSize;
-    vec2 rel = coord - u_Pos;
-    float theta = atan(rel.y, rel.x);
-    float ox = degrees(theta) / 360.0;
-    if (ox < 0) {
-        ox += 1.0;
-    }
-    float r = length(rel);
-    float occl = texture(t_Texture, vec2(ox, 0.5)).r * 2.0;
-    float intensity = 0.0;
-    if (r < occl) {
-        vec2 g = u_ScreenSize / u_ScreenSize.y;
-        float p = u_Strength + u_Glow;
-        float d = distance(g * coord, g * u_Pos);
-        intensity = clamp(p/(d*d), 0.0, 0.6);
-    }
-    Target0 = mix(vec4(0.0, 0.0, 0.0, 1.0), vec4(u_LightColor.rgb, 1.0), intensity);
+/// Where `point` on the field is in the window, the way the light shaders want it:
+/// between 0 and 1 and starting from the lower left corner
+fn light_pos(ctx: &Context, point: na::Point2<f32>) -> [f32; 2] {
+    let screen = graphics::screen_coordinates(ctx);
+    [
+        (point.x - screen.x) / screen.w,
+        1.0 - (point.y - screen.y) / screen.h,
+    ]
 }
-";
 
-// Now we are mostly done with the scary code above
+/// Picks the option `step` places after `current`, wrapping around at the ends
+fn cycle<T: PartialEq + Copy>(options: &[T], current: T, step: isize) -> T {
+    let i = options.iter().position(|&o| o == current).unwrap_or(0) as isize;
+    options[(i + step).rem_euclid(options.len() as isize) as usize]
+}
 
-/// Clamps a value between a set amount of values
-fn clamp(value: &mut f32, low: f32, high: f32) {
-    if *value < low {
-        *value = low;
-    } else if *value > high {
-        *value = high;
-    }
+/// Seconds as minutes and seconds, like 2:05
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
 }
 
-/// Moves the racket up and down
-fn move_racket(pos: &mut na::Point2<f32>, keycode: KeyCode, y_dir: f32, ctx: &mut Context) {
-    let screen_h = graphics::drawable_size(ctx).1;
-    let dt = timer::delta(ctx).as_secs_f32();
+/// The seed for the computer player on `side`, so both sides miss differently
+fn controller_seed(match_seed: u64, side: usize) -> u64 {
+    match_seed.wrapping_add(side as u64 + 1)
+}
 
-    if keyboard::is_key_pressed(ctx, keycode) {
-        pos.y -= PLAYER_SPEED * dt * y_dir;
+/// The color of `player`'s racket, and of the ball and its light after they hit it
+fn player_color(player: Player) -> [f32; 4] {
+    match player {
+        Player::One => [0.0, 0.0, 1.0, 1.0],
+        Player::Two => [1.0, 0.0, 0.0, 1.0],
     }
-    clamp(
-        &mut pos.y,
-        RACKET_HEIGHT_HALF,
-        screen_h - RACKET_HEIGHT_HALF,
-    );
 }
 
-/// Randomizes the starting orientation
-fn randomize_vec(vec: &mut na::Vector2<f32>, x: f32, y: f32) {
-    let mut rng = thread_rng();
-    vec.x = match rng.gen_bool(0.5) {
-        true => x,
-        false => -x,
-    };
-    vec.y = match rng.gen_bool(0.5) {
-        true => y,
-        false => -y,
-    };
+/// Linear interpolation between two points, `t` of 0.0 gives `a` and 1.0 gives `b`
+fn lerp(a: na::Point2<f32>, b: na::Point2<f32>, t: f32) -> na::Point2<f32> {
+    a + (b - a) * t
 }
 
 struct MainState {
-    player_1_pos: na::Point2<f32>,
-    player_2_pos: na::Point2<f32>,
+    sim: Simulation,
+    /// The simulation as it was one tick ago, used to smooth out the rendering
+    prev_sim: Simulation,
+    /// How many times a second the game logic runs right now,
+    /// replays and online matches bring their own
+    tick_rate: u32,
+    /// What local matches run at, from `--tick-rate` or the settings file
+    match_tick_rate: u32,
+    rules: Ruleset,
+    /// Every match starts from this seed when it's set, from `--seed` or the
+    /// settings file. Otherwise each one gets a random seed.
+    seed: Option<u64>,
+    /// Show the seed and a few other numbers in the corner
+    debug: bool,
+    /// What is controlling each racket, player 1 first
+    controller_kinds: [ControllerKind; 2],
+    controllers: [Box<dyn Opponent>; 2],
+    /// Top speed of each racket when it follows the mouse, one of `MOUSE_SPEEDS`
+    mouse_speeds: [f32; 2],
     racket_mesh: graphics::Mesh,
     racket_mesh_2: graphics::Mesh,
-    ball_pos: na::Point2<f32>,
-    ball_vel: na::Vector2<f32>,
     ball_mesh: graphics::Mesh,
-    middle_mesh: graphics::Mesh,
-    player_1_score: i32,
-    player_2_score: i32,
+    /// A white 1 by 1 square, stretched and tinted into the middle line,
+    /// the letterbox bars and the overlay that darkens the game behind the menus
+    square_mesh: graphics::Mesh,
+    scenes: SceneStack,
+    controls: Controls,
+    /// The control waiting for a key press on the controls screen
+    rebinding: Option<Control>,
+    /// Shown at the bottom of the controls screen, e.g. when a key is already taken
+    controls_message: Option<String>,
+    gamepads: Gamepads,
+    /// Something that just happened, like a gamepad joining, and the seconds left to show it
+    notice: Option<(String, f32)>,
+    /// The online match, from when we start hosting or joining until it's over
+    net: Option<NetSession>,
+    /// The `pong-server` we're playing on, if we are
+    dedicated: Option<ServerConnection>,
+    /// Looks for games on the network while the lobby is open
+    lobby: Option<Listener>,
+    /// Tells the network about the game we're hosting, so people can join it and then watch it
+    announcer: Option<Announcer>,
+    /// Spectators see the whole field lit up instead of following the ball
+    overview: bool,
+    /// The match being played offline, recorded as it goes
+    recording: Option<Replay>,
+    /// The recorded match being watched with `--replay`
+    playback: Option<Playback>,
+    /// `settings.toml` as it was loaded, the window size, fullscreen, lighting
+    /// and mouse players get written back into it when the game closes
+    settings: Settings,
+    /// The graphics settings in use, with the command line and whatever the player changed since.
+    /// The size is the window's the last time it wasn't fullscreen.
+    graphics: GraphicsSettings,
+    /// The graphics settings the game started with, to tell if the player changed them
+    started_graphics: GraphicsSettings,
     background: graphics::Image,
+    /// The light following the ball
     torch: Light,
-    foreground: Canvas,
-    occlusions: Canvas,
-    shadows: Canvas,
-    lights: Canvas,
-    occlusions_shader: Shader<Light>,
-    shadows_shader: Shader<Light>,
-    lights_shader: Shader<Light>,
+    /// Lights fading out where something just happened, oldest first
+    flashes: Vec<Flash>,
+    /// Sparks off the rackets, dust off the walls and bursts at the goals
+    particles: Particles,
+    /// `None` draws the game without light or shadows, when they're turned off or don't work
+    lighting: Option<Lighting>,
+    /// Bloom and the other effects over the whole picture, `None` when they're
+    /// all off or don't work
+    post_processing: Option<PostProcess>,
+    /// Notices when the shaders in resources are saved, with `--dev`
+    shader_watcher: Option<shaders::Watcher>,
+    /// Why the saved shaders didn't compile, shown until they do
+    shader_error: Option<String>,
 }
 
 impl MainState {
-    pub fn new(ctx: &mut Context) -> GameResult<MainState> {
-        let (screen_w, screen_h) = graphics::drawable_size(ctx);
-        let (screen_w_half, screen_h_half) = (screen_w * 0.5, screen_h * 0.5);
-
-        let mut ball_vel = na::Vector2::new(0.0, 0.0);
-        randomize_vec(&mut ball_vel, BALL_SPEED, BALL_SPEED);
+    pub fn new(
+        ctx: &mut Context,
+        args: &Args,
+        settings: Settings,
+        resource_dir: &path::Path,
+    ) -> GameResult<MainState> {
+        let graphics_settings = settings.graphics.with_args(args);
 
         let racket_rect = graphics::Rect::new(
             -RACKET_WIDTH_HALF,
@@ -255,14 +252,14 @@ impl MainState {
             ctx,
             graphics::DrawMode::fill(),
             racket_rect,
-            graphics::Color::new(0.0, 0.0, 1.0, 1.0),
+            player_color(Player::One).into(),
         )?;
 
         let racket_mesh_2 = graphics::Mesh::new_rectangle(
             ctx,
             graphics::DrawMode::fill(),
             racket_rect,
-            graphics::Color::new(1.0, 0.0, 0.0, 1.0),
+            player_color(Player::Two).into(),
         )?;
 
         let ball_mesh = graphics::Mesh::new_circle(
@@ -271,321 +268,1432 @@ impl MainState {
             Point2::new(-BALL_SIZE_HALF, -BALL_SIZE_HALF),
             BALL_SIZE,
             0.1,
-            graphics::Color::new(1.0, 0.0, 1.0, 1.0),
+            NEUTRAL_COLOR.into(),
         )?;
 
-        let middle_rect = graphics::Rect::new(-MIDDLE_LINE_W * 0.5, 0.0, MIDDLE_LINE_W, screen_h);
-        let middle_mesh = graphics::Mesh::new_rectangle(
+        let square_mesh = graphics::Mesh::new_rectangle(
             ctx,
             graphics::DrawMode::fill(),
-            middle_rect,
+            graphics::Rect::new(0.0, 0.0, 1.0, 1.0),
             graphics::WHITE,
         )?;
 
-        let screen_size = {
-            let size = graphics::drawable_size(ctx);
-            [size.0 as f32, size.1 as f32]
-        };
         //add a background image
         let background = graphics::Image::new(ctx, "/bg_top.png")?;
         //set the light
         let torch = Light {
-            pos: [screen_w_half, screen_h_half],
-            light_color: [1.0, 0.0, 1.0, 1.0],
-            shadow_color: AMBIENT_COLOR,
-            screen_size,
+            pos: [0.5, 0.5],
+            color: NEUTRAL_COLOR,
             glow: 0.0,
             strength: LIGHT_STRENGTH,
         };
-        let foreground = Canvas::with_window_size(ctx)?;
-        let occlusions = Canvas::new(ctx, LIGHT_RAY_COUNT, 1, conf::NumSamples::One)?;
-        let mut shadows = Canvas::with_window_size(ctx)?;
-        // The shadow map will be drawn on top using the multiply blend mode
-        shadows.set_blend_mode(Some(BlendMode::Multiply));
-        let mut lights = Canvas::with_window_size(ctx)?;
-        // The light map will be drawn on top using the add blend mode
-        lights.set_blend_mode(Some(BlendMode::Add));
-
-        let occlusions_shader = Shader::from_u8(
-            ctx,
-            VERTEX_SHADER_SOURCE,
-            OCCLUSIONS_SHADER_SOURCE,
-            torch,
-            "Light",
-            None,
-        )
-        .unwrap();
-        let shadows_shader = Shader::from_u8(
-            ctx,
-            VERTEX_SHADER_SOURCE,
-            SHADOWS_SHADER_SOURCE,
-            torch,
-            "Light",
-            None,
-        )
-        .unwrap();
-        let lights_shader = Shader::from_u8(
-            ctx,
-            VERTEX_SHADER_SOURCE,
-            LIGHTS_SHADER_SOURCE,
-            torch,
-            "Light",
-            Some(&[BlendMode::Add]),
-        )
-        .unwrap();
 
-        Ok(MainState {
-            player_1_pos: na::Point2::new(RACKET_WIDTH_HALF + PADDING, screen_h_half),
-            player_2_pos: na::Point2::new(screen_w - RACKET_WIDTH_HALF - PADDING, screen_h_half),
+        let seed = args.seed.or(settings.seed);
+        let tick_rate = match args.tick_rate.or(settings.tick_rate).map(check_tick_rate) {
+            Some(Ok(tick_rate)) => tick_rate,
+            Some(Err(e)) => {
+                eprintln!("{}, using {}", e, TICK_RATE);
+                TICK_RATE
+            }
+            None => TICK_RATE,
+        };
+        let rules = Ruleset {
+            serve_model: settings.serve.model(),
+            ..Ruleset::default()
+        };
+        let sim = Simulation::with_seed(FIELD_W, FIELD_H, rules, seed.unwrap_or_else(rand::random));
+        let players = &settings.players;
+        let controller_kinds = [
+            players.player_1.controller_kind(ControllerKind::Human),
+            players
+                .player_2
+                .controller_kind(ControllerKind::Predictor(Level::Medium)),
+        ];
+        let mouse_speeds = [
+            players.player_1.mouse_speed(),
+            players.player_2.mouse_speed(),
+        ];
+        let controllers = [
+            controller_kinds[0].build(controller_seed(sim.seed, 0)),
+            controller_kinds[1].build(controller_seed(sim.seed, 1)),
+        ];
+
+        let mut state = MainState {
+            prev_sim: sim.clone(),
+            sim,
+            tick_rate,
+            match_tick_rate: tick_rate,
+            rules,
+            seed,
+            debug: false,
+            controller_kinds,
+            controllers,
+            mouse_speeds,
             racket_mesh,
             racket_mesh_2,
-            ball_pos: na::Point2::new(screen_w_half, screen_h_half),
-            ball_vel,
             ball_mesh,
-            middle_mesh,
-            player_1_score: 0,
-            player_2_score: 0,
+            square_mesh,
+            scenes: SceneStack::new(Scene::Title),
+            controls: Controls::load(),
+            rebinding: None,
+            controls_message: None,
+            gamepads: Gamepads::default(),
+            notice: None,
+            net: None,
+            dedicated: None,
+            lobby: None,
+            announcer: None,
+            overview: false,
+            recording: None,
+            playback: None,
+            settings,
+            started_graphics: graphics_settings.clone(),
+            graphics: graphics_settings.clone(),
             background,
             torch,
-            foreground,
-            occlusions,
-            shadows,
-            lights,
-            occlusions_shader,
-            shadows_shader,
-            lights_shader,
-        })
-    }
-
-    //se example and official documentation
-    fn render_light(
-        &mut self,
-        ctx: &mut Context,
-        light: Light,
-        origin: DrawParam,
-        canvas_origin: DrawParam,
-    ) -> GameResult {
-        let size = graphics::size(ctx);
-        // Now we want to run the occlusions shader to calculate our 1D shadow
-        // distances into the `occlusions` canvas.
-        graphics::set_canvas(ctx, Some(&self.occlusions));
-        {
-            let _shader_lock = graphics::use_shader(ctx, &self.occlusions_shader);
+            flashes: Vec::new(),
+            particles: Particles::new(ctx, graphics_settings.max_particles())?,
+            lighting: None,
+            post_processing: None,
+            shader_watcher: if args.dev {
+                let files = [&lighting::SHADER_FILES[..], &postprocess::SHADER_FILES[..]].concat();
+                Some(shaders::Watcher::new(resource_dir, &files))
+            } else {
+                None
+            },
+            shader_error: None,
+        };
+        state.set_lighting(ctx, graphics_settings.lighting);
+        state.set_post_processing(ctx);
+        if let Some(mode) = &args.net {
+            state.start_online(mode);
+        }
+        if let Some(path) = &args.replay {
+            match Replay::load(path) {
+                Ok(replay) => state.start_playback(ctx, replay)?,
+                Err(e) => state.show_notice(format!("Could not load the replay: {}", e)),
+            }
+        }
+        Ok(state)
+    }
 
-            self.occlusions_shader.send(ctx, light)?;
-            graphics::draw(ctx, &self.foreground, canvas_origin)?;
+    /// Starts hosting, joining or connecting to a server for an online match
+    fn start_online(&mut self, mode: &NetMode) {
+        let result = match mode {
+            NetMode::Host(port) => {
+                self.restart();
+                NetSession::host(*port, &self.sim, self.tick_rate)
+                    .map(|session| self.net = Some(session))
+            }
+            NetMode::Join(address) => {
+                NetSession::join(address).map(|session| self.net = Some(session))
+            }
+            NetMode::Connect(address) => ServerConnection::connect(address)
+                .map(|connection| self.dedicated = Some(connection)),
+            NetMode::Watch(address) => {
+                ServerConnection::watch(address).map(|connection| self.dedicated = Some(connection))
+            }
+        };
+        match result {
+            Ok(()) => self.scenes.push(Scene::Connecting),
+            Err(e) => return self.show_notice(format!("Could not go online: {}", e)),
         }
 
-        // Now we render our shadow map and light map into their respective
-        // canvases based on the occlusion map. These will then be drawn onto
-        // the final render target using appropriate blending modes.
-        graphics::set_canvas(ctx, Some(&self.shadows));
-        {
-            let _shader_lock = graphics::use_shader(ctx, &self.shadows_shader);
+        if let Some(netplay::Status::Waiting(port)) = self.net.as_ref().map(NetSession::status) {
+            let announcement = Announcement {
+                id: rand::random(),
+                kind: HostKind::Peer,
+                name: discovery::host_name(),
+                port: *port,
+                rules: self.sim.rules,
+                open_slots: 1,
+            };
+            // not being found on the network is no reason to stop hosting
+            match Announcer::new(announcement) {
+                Ok(announcer) => self.announcer = Some(announcer),
+                Err(e) => eprintln!("could not announce the game on the network: {}", e),
+            }
+        }
+    }
 
-            let param = origin.scale(Vector2::new(
-                (size.0 as f32) / (LIGHT_RAY_COUNT as f32),
-                size.1 as f32,
-            ));
-            self.shadows_shader.send(ctx, light)?;
-            graphics::draw(ctx, &self.occlusions, param)?;
+    /// Hangs up on the online match, if there is one
+    fn leave_online(&mut self) {
+        // only the ticks both sides agreed on end up in the replay
+        self.finish_recording();
+        self.net = None;
+        self.dedicated = None;
+        self.announcer = None;
+        self.tick_rate = self.match_tick_rate;
+        self.restart();
+    }
+
+    fn is_online(&self) -> bool {
+        self.net.is_some() || self.dedicated.is_some()
+    }
+
+    /// We're watching someone else's match
+    fn spectating(&self) -> bool {
+        self.dedicated
+            .as_ref()
+            .is_some_and(|connection| connection.spectating)
+    }
+
+    /// How many people are watching the online match we're in
+    fn spectators(&self) -> u8 {
+        match (&self.net, &self.dedicated) {
+            (Some(net), _) => net.spectators(),
+            (_, Some(connection)) => connection.spectators(),
+            _ => 0,
         }
-        graphics::set_canvas(ctx, Some(&self.lights));
-        {
-            let _shader_lock = graphics::use_shader(ctx, &self.lights_shader);
+    }
 
-            let param = origin.scale(Vector2::new(
-                (size.0 as f32) / (LIGHT_RAY_COUNT as f32),
-                size.1 as f32,
-            ));
-            self.lights_shader.send(ctx, light)?;
-            graphics::draw(ctx, &self.occlusions, param)?;
+    /// The racket we control in an online match that has started
+    fn local_player(&self) -> Option<Player> {
+        if let Some(net) = &self.net {
+            if *net.status() == netplay::Status::Playing {
+                return Some(net.local_player);
+            }
+        }
+        if let Some(connection) = &self.dedicated {
+            let connecting = matches!(connection.status(), remote::Status::Connecting(_));
+            if connecting || connection.spectating {
+                return None;
+            }
+            return Some(connection.local_player);
+        }
+        None
+    }
+
+    /// Keeps the online connection going and reacts to it starting or ending
+    fn update_online(&mut self) {
+        // stop looking for games once we're in one
+        if !matches!(self.scenes.top(), Scene::Lobby | Scene::Connecting) {
+            self.lobby = None;
+        }
+        if let Some(lobby) = &mut self.lobby {
+            lobby.update();
+            if self.scenes.top() == Scene::Lobby {
+                let count = self.menu().len();
+                self.scenes.clamp_selection(count);
+            }
+        }
+        if let Some(announcer) = &mut self.announcer {
+            announcer.update();
+        }
+
+        let mut started = self.net.as_mut().and_then(NetSession::poll);
+        if let Some(connection) = &mut self.dedicated {
+            started = connection.poll();
+        }
+        if let Some(sim) = started {
+            // someone joined, keep telling the network about it so people can come and watch
+            if let Some(announcer) = &mut self.announcer {
+                announcer.announcement.open_slots = 0;
+            }
+            self.prev_sim = sim.clone();
+            self.sim = sim;
+            // both sides have to tick together, the host or server decides how fast
+            if let Some(net) = &self.net {
+                self.tick_rate = net.tick_rate();
+            } else if let Some(connection) = &self.dedicated {
+                self.tick_rate = connection.tick_rate();
+            }
+            // a server starts the match once the other player is there, until then we keep waiting
+            if self.net.is_some() {
+                self.scenes.replace(Scene::Playing);
+            }
+            match self.local_player() {
+                Some(player) => {
+                    self.show_notice(format!("Connected, you are player {}", player.index() + 1))
+                }
+                None => {
+                    let camera = self.key_hint(Control::Camera);
+                    self.show_notice(format!("Watching, press {} to switch the camera", camera));
+                }
+            }
+        }
+
+        if let Some(connection) = &self.dedicated {
+            let scene = self.scenes.top();
+            // the server starts the next match by itself a little after the last one ended
+            let new_match = scene == Scene::GameOver && self.sim.winner.is_none();
+            if *connection.status() == remote::Status::Playing
+                && (scene == Scene::Connecting || new_match)
+            {
+                self.scenes.replace(Scene::Playing);
+            }
+        }
+
+        let disconnected = match (&self.net, &self.dedicated) {
+            (Some(net), _) => match net.status() {
+                netplay::Status::Disconnected(reason) => Some(reason.clone()),
+                _ => None,
+            },
+            (_, Some(connection)) => match connection.status() {
+                remote::Status::Disconnected(reason) => Some(reason.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(reason) = disconnected {
+            self.leave_online();
+            // back to the lobby or title it was started from if it never got going
+            if self.scenes.top() == Scene::Connecting {
+                self.scenes.pop();
+            } else {
+                self.scenes.replace(Scene::Title);
+            }
+            self.show_notice(reason);
+        }
+    }
+
+    /// What the player on the keys, gamepad or mouse of `side` wants `player`'s racket to do
+    fn held_input(&self, ctx: &Context, side: usize, player: Player, dt: f32) -> f32 {
+        if self.controller_kinds[side] == ControllerKind::Mouse {
+            // the mouse is in window coordinates, the racket is on the field
+            let screen = graphics::screen_coordinates(ctx);
+            let window_h = graphics::drawable_size(ctx).1;
+            let mouse_y = screen.y + mouse::position(ctx).y * screen.h / window_h;
+            return opponent::follow_pointer(
+                &self.sim,
+                player,
+                mouse_y,
+                self.mouse_speeds[side],
+                dt,
+            );
+        }
+        let (up, down) = match side {
+            0 => (Control::P1Up, Control::P1Down),
+            _ => (Control::P2Up, Control::P2Down),
+        };
+        // keys and gamepad together, so a gamepad player can still fall back on the keyboard
+        let mut axis = self.controls.axis(ctx, up, down) + self.gamepads.axis(ctx, side);
+        clamp(&mut axis, -1.0, 1.0);
+        axis
+    }
+
+    /// Starts a new match with the current rules
+    fn restart(&mut self) {
+        self.finish_recording();
+        let (field_w, field_h) = (self.sim.field_w, self.sim.field_h);
+        let seed = self.seed.unwrap_or_else(rand::random);
+        self.sim = Simulation::with_seed(field_w, field_h, self.rules, seed);
+        self.prev_sim = self.sim.clone();
+        // computer players start over too, so a fixed seed plays out the same every time
+        for side in 0..2 {
+            self.controllers[side] = self.controller_kinds[side].build(controller_seed(seed, side));
+        }
+        self.recording = Some(Replay::new(&self.sim, self.tick_rate));
+    }
+
+    /// Saves the replay of the match that just ended, or was left unfinished
+    fn finish_recording(&mut self) {
+        let recording = self.recording.take();
+        let replay = match &mut self.net {
+            Some(net) => net.replay(),
+            None => recording,
+        };
+        let replay = match replay.filter(|replay| !replay.inputs.is_empty()) {
+            Some(replay) => replay,
+            None => return,
+        };
+        let dir = match config::replay_dir() {
+            Some(dir) => dir,
+            None => return,
+        };
+        let path = Replay::new_path(&dir);
+        match fs::create_dir_all(&dir).and_then(|()| replay.save(&path)) {
+            Ok(()) => println!("saved the replay to {}", path.display()),
+            Err(e) => eprintln!("could not save the replay to {}: {}", path.display(), e),
+        }
+    }
+
+    /// Starts watching a recorded match
+    fn start_playback(&mut self, ctx: &mut Context, replay: Replay) -> GameResult {
+        self.recording = None;
+        self.sim = replay.start();
+        self.prev_sim = self.sim.clone();
+        let playback = Playback::new(replay);
+        self.tick_rate = playback.tick_rate();
+        self.playback = Some(playback);
+        self.set_ball_color(ctx, None)?;
+        self.scenes.replace(Scene::Playing);
+        Ok(())
+    }
+
+    fn stop_playback(&mut self) {
+        if self.playback.take().is_some() {
+            self.tick_rate = self.match_tick_rate;
+            self.restart();
+        }
+    }
+
+    /// Shows the match where a replay was skipped to
+    fn after_seek(&mut self, ctx: &mut Context) -> GameResult {
+        // don't draw the ball sliding over from where it was
+        self.prev_sim = self.sim.clone();
+        self.particles.clear();
+        self.set_ball_color(ctx, self.sim.last_hitter)
+    }
+
+    /// Colors the ball and its light after whoever hit it last
+    fn set_ball_color(&mut self, ctx: &mut Context, hitter: Option<Player>) -> GameResult {
+        let color = hitter.map_or(NEUTRAL_COLOR, player_color);
+        self.torch.color = color;
+        self.ball_mesh = graphics::Mesh::new_circle(
+            ctx,
+            graphics::DrawMode::fill(),
+            Point2::new(-BALL_SIZE_HALF, -BALL_SIZE_HALF),
+            BALL_SIZE,
+            0.1,
+            color.into(),
+        )?;
+        Ok(())
+    }
+
+    /// The first key bound to `control`, to tell the player what to press
+    fn key_hint(&self, control: Control) -> String {
+        self.controls
+            .keys(control)
+            .first()
+            .map_or("?".to_string(), |&key| key_name(key))
+    }
+
+    /// What a menu entry says, including the current value for options
+    fn action_label(&self, action: Action) -> String {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        match action {
+            Action::Play => "Play".to_string(),
+            Action::Resume => "Resume".to_string(),
+            Action::Restart if self.playback.is_some() => "Watch again".to_string(),
+            Action::Restart if self.scenes.top() == Scene::GameOver => "Play again".to_string(),
+            Action::Restart => "Restart".to_string(),
+            Action::Options => "Options".to_string(),
+            Action::Lobby => "Play on the network".to_string(),
+            Action::HostGame => "Host a game".to_string(),
+            Action::JoinGame(i) => match self.lobby.as_ref().and_then(|l| l.games().get(i)) {
+                Some(game) => {
+                    let found = &game.announcement;
+                    let slots = match found.open_slots {
+                        0 => "full, watch".to_string(),
+                        1 => "1 free slot".to_string(),
+                        n => format!("{} free slots", n),
+                    };
+                    let server = match found.kind {
+                        HostKind::Peer => "",
+                        HostKind::Server => " [server]",
+                    };
+                    format!(
+                        "{}{}: first to {}, best of {}, {} ({})",
+                        found.name,
+                        server,
+                        found.rules.win_score,
+                        found.rules.best_of,
+                        found.rules.serve.name(),
+                        slots
+                    )
+                }
+                None => "Gone".to_string(),
+            },
+            Action::ToTitle => "Quit to title".to_string(),
+            Action::Back => "Back".to_string(),
+            Action::Quit => "Quit".to_string(),
+            Action::Player1 | Action::Player2 => {
+                let side = if action == Action::Player1 { 0 } else { 1 };
+                let gamepad = if self.gamepads.has_gamepad(side) {
+                    " + gamepad"
+                } else {
+                    ""
+                };
+                format!(
+                    "Player {}: {}{}",
+                    side + 1,
+                    self.controller_kinds[side].name(),
+                    gamepad
+                )
+            }
+            Action::MouseSpeed1 | Action::MouseSpeed2 => {
+                let side = if action == Action::MouseSpeed1 { 0 } else { 1 };
+                format!(
+                    "Player {} mouse speed: {}%",
+                    side + 1,
+                    (self.mouse_speeds[side] * 100.0) as u32
+                )
+            }
+            Action::Serve => format!("Serve: {}", self.rules.serve.name()),
+            Action::ServeToConceder => format!(
+                "Serve at whoever conceded: {}",
+                on_off(self.rules.serve_model.toward_conceder)
+            ),
+            Action::WinScore => format!("First to: {}", self.rules.win_score),
+            Action::WinByTwo => format!("Win by two: {}", on_off(self.rules.win_by_two)),
+            Action::BestOf => format!("Best of: {}", self.rules.best_of),
+            Action::Lighting
+                if self.graphics.lighting != Quality::Off && self.lighting.is_none() =>
+            {
+                format!("Lighting: {} (not supported)", self.graphics.lighting)
+            }
+            Action::Lighting => format!("Lighting: {}", self.graphics.lighting),
+            Action::Controls => "Controls".to_string(),
+            Action::Rebind(control) if self.rebinding == Some(control) => {
+                format!("{}: press a key", control.name())
+            }
+            Action::Rebind(control) => {
+                let keys: Vec<String> = self
+                    .controls
+                    .keys(control)
+                    .iter()
+                    .map(|&key| key_name(key))
+                    .collect();
+                format!("{}: {}", control.name(), keys.join(", "))
+            }
+            Action::ResetControls => "Reset to defaults".to_string(),
+        }
+    }
+
+    /// Does what a menu entry says. `step` is which way options get cycled,
+    /// so left and right can go through them in both directions.
+    fn activate(&mut self, ctx: &mut Context, action: Action, step: isize) {
+        match action {
+            Action::Restart if self.playback.is_some() => {
+                if let Some(playback) = &mut self.playback {
+                    playback.rewind(&mut self.sim);
+                }
+                self.scenes.replace(Scene::Playing);
+                // the menus don't hand out errors, and this only fails if the GPU does
+                let _ = self.after_seek(ctx);
+            }
+            // both sides would have to agree on a new match, so online it's one match per connection
+            Action::Restart if self.is_online() => {
+                self.show_notice("Online matches can't be restarted".to_string())
+            }
+            Action::Play | Action::Restart => {
+                self.scenes.replace(Scene::Playing);
+                self.restart();
+            }
+            Action::Back if self.scenes.top() == Scene::Connecting => {
+                self.leave_online();
+                self.scenes.pop();
+            }
+            Action::Back if self.scenes.top() == Scene::Lobby => {
+                self.lobby = None;
+                self.scenes.pop();
+            }
+            Action::Lobby => match Listener::new() {
+                Ok(listener) => {
+                    self.lobby = Some(listener);
+                    self.scenes.push(Scene::Lobby);
+                }
+                Err(e) => self.show_notice(format!("Could not look for games: {}", e)),
+            },
+            // port 0 picks any free port, so a few copies of the game on one computer can all host
+            Action::HostGame => self.start_online(&NetMode::Host(0)),
+            Action::JoinGame(i) => {
+                let game = self.lobby.as_ref().and_then(|lobby| lobby.games().get(i));
+                if let Some(game) = game {
+                    let address = game.address.to_string();
+                    let mode = match (game.announcement.kind, game.announcement.open_slots) {
+                        (_, 0) => NetMode::Watch(address),
+                        (HostKind::Peer, _) => NetMode::Join(address),
+                        (HostKind::Server, _) => NetMode::Connect(address),
+                    };
+                    self.start_online(&mode);
+                }
+            }
+            Action::Resume | Action::Back => self.scenes.pop(),
+            Action::Options => self.scenes.push(Scene::Options),
+            Action::ToTitle => {
+                self.scenes.replace(Scene::Title);
+                self.stop_playback();
+                self.leave_online();
+            }
+            Action::Quit => event::quit(ctx),
+            Action::Player1 | Action::Player2 => {
+                let side = if action == Action::Player1 { 0 } else { 1 };
+                self.controller_kinds[side] =
+                    cycle(&ControllerKind::all(), self.controller_kinds[side], step);
+                let seed = controller_seed(self.sim.seed, side);
+                self.controllers[side] = self.controller_kinds[side].build(seed);
+            }
+            Action::MouseSpeed1 | Action::MouseSpeed2 => {
+                let side = if action == Action::MouseSpeed1 { 0 } else { 1 };
+                self.mouse_speeds[side] = cycle(&MOUSE_SPEEDS, self.mouse_speeds[side], step);
+            }
+            // rule changes only kick in from the next match
+            Action::Serve => self.rules.serve = cycle(&ServeRule::ALL, self.rules.serve, step),
+            Action::ServeToConceder => {
+                let model = &mut self.rules.serve_model;
+                model.toward_conceder = !model.toward_conceder;
+            }
+            Action::WinScore => {
+                self.rules.win_score = cycle(&[5, 11, 21], self.rules.win_score, step)
+            }
+            Action::WinByTwo => self.rules.win_by_two = !self.rules.win_by_two,
+            Action::BestOf => self.rules.best_of = cycle(&[1, 3, 5], self.rules.best_of, step),
+            Action::Lighting => {
+                let quality = cycle(&Quality::ALL, self.graphics.lighting, step);
+                self.set_lighting(ctx, quality);
+            }
+            Action::Controls => {
+                self.controls_message = None;
+                self.scenes.push(Scene::Controls);
+            }
+            Action::Rebind(control) => {
+                self.rebinding = Some(control);
+                self.controls_message = None;
+            }
+            Action::ResetControls => {
+                self.controls = Controls::default();
+                self.controls.save();
+                self.controls_message = Some("Controls reset to defaults".to_string());
+            }
+        }
+    }
+
+    fn show_notice(&mut self, text: String) {
+        self.notice = Some((text, NOTICE_TIME));
+    }
+
+    /// The entries of the current scene's menu, top to bottom
+    fn menu(&self) -> Vec<Action> {
+        let scene = self.scenes.top();
+        let mut menu = Vec::new();
+        if let (Scene::Lobby, Some(lobby)) = (scene, &self.lobby) {
+            menu.extend((0..lobby.games().len()).map(Action::JoinGame));
+        }
+        menu.extend_from_slice(scene.actions());
+        menu
+    }
+
+    /// Reacts to a key or gamepad button that is bound to `pressed`
+    fn handle_controls(&mut self, ctx: &mut Context, pressed: &[Control]) {
+        let pressed = |control| pressed.contains(&control);
+        if pressed(Control::Debug) {
+            self.debug = !self.debug;
+        }
+        if pressed(Control::Fullscreen) {
+            self.toggle_fullscreen(ctx);
+        }
+        let scene = self.scenes.top();
+        if scene == Scene::Playing {
+            if pressed(Control::Pause) {
+                self.scenes.push(Scene::Paused);
+            }
+            if pressed(Control::Camera) && self.spectating() {
+                self.overview = !self.overview;
+            }
+            if let Some(playback) = &mut self.playback {
+                // nobody is playing, so the menu keys are free to control the replay
+                if pressed(Control::Confirm) {
+                    playback.paused = !playback.paused;
+                } else if pressed(Control::MenuUp) {
+                    playback.speed = cycle(&playback::SPEEDS, playback.speed, 1);
+                } else if pressed(Control::MenuDown) {
+                    playback.speed = cycle(&playback::SPEEDS, playback.speed, -1);
+                } else if pressed(Control::MenuLeft) || pressed(Control::MenuRight) {
+                    let skip = if pressed(Control::MenuLeft) {
+                        -REPLAY_SKIP
+                    } else {
+                        REPLAY_SKIP
+                    };
+                    playback.seek(&mut self.sim, skip);
+                    let _ = self.after_seek(ctx);
+                }
+            }
+            return;
+        }
+
+        let menu = self.menu();
+        let selected = match menu.get(self.scenes.selected()) {
+            Some(&action) => action,
+            None => return,
+        };
+        // left and right only change options, they don't leave the menu
+        let changes_option =
+            scene == Scene::Options && selected != Action::Back && selected != Action::Controls;
+
+        if pressed(Control::MenuUp) {
+            self.scenes.move_selection(-1, menu.len());
+        } else if pressed(Control::MenuDown) {
+            self.scenes.move_selection(1, menu.len());
+        } else if pressed(Control::Confirm) {
+            self.activate(ctx, selected, 1);
+        } else if pressed(Control::MenuLeft) && changes_option {
+            self.activate(ctx, selected, -1);
+        } else if pressed(Control::MenuRight) && changes_option {
+            self.activate(ctx, selected, 1);
+        } else if pressed(Control::Back) {
+            match scene {
+                Scene::Title => event::quit(ctx),
+                Scene::GameOver => self.activate(ctx, Action::ToTitle, 1),
+                Scene::Connecting | Scene::Lobby => self.activate(ctx, Action::Back, 1),
+                _ => self.scenes.pop(),
+            }
+        }
+    }
+
+    /// Draws the seed and a few other numbers that help when something goes wrong
+    fn draw_debug(&self, ctx: &mut Context) -> GameResult {
+        let seed = match &self.dedicated {
+            Some(_) => "seed known only to the server".to_string(),
+            None => format!("seed {}", self.sim.seed),
+        };
+        let lines = [
+            seed,
+            format!(
+                "{:.0} fps, {} ticks a second",
+                timer::fps(ctx),
+                self.tick_rate
+            ),
+            format!(
+                "ball at {:.0}, {:.0} going {:.0}",
+                self.sim.ball_pos.x,
+                self.sim.ball_pos.y,
+                self.sim.ball_vel.norm()
+            ),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            let text = graphics::Text::new(
+                graphics::TextFragment::new(line.as_str()).scale(graphics::Scale::uniform(14.0)),
+            );
+            let dest = Point2::new(10.0, 10.0 + i as f32 * 16.0);
+            graphics::draw(ctx, &text, DrawParam::new().dest(dest))?;
+        }
+        Ok(())
+    }
+
+    /// Draws the menu of the current scene on top of the game
+    fn draw_menu(&self, ctx: &mut Context) -> GameResult {
+        let scene = self.scenes.top();
+        if scene == Scene::Playing {
+            return Ok(());
+        }
+        let screen_h = self.sim.field_h;
+
+        let overlay = DrawParam::new()
+            .scale(Vector2::new(self.sim.field_w, self.sim.field_h))
+            .color(graphics::Color::new(0.0, 0.0, 0.0, 0.6));
+        graphics::draw(ctx, &self.square_mesh, overlay)?;
+
+        let heading = match (scene, self.sim.winner) {
+            (Scene::GameOver, Some(Player::One)) => "Player 1 wins!",
+            (Scene::GameOver, Some(Player::Two)) => "Player 2 wins!",
+            _ => scene.heading(),
+        };
+        draw_centered_text(ctx, heading, 48.0, screen_h * 0.25, graphics::WHITE)?;
+
+        // long menus get squeezed together so they still fit
+        let actions = self.menu();
+        let spacing = (screen_h * 0.5 / actions.len() as f32).min(32.0);
+        let text_size = (spacing * 0.75).min(24.0);
+        for (i, &action) in actions.iter().enumerate() {
+            let y = screen_h * 0.4 + i as f32 * spacing;
+            if i == self.scenes.selected() {
+                let label = format!("> {} <", self.action_label(action));
+                let yellow = graphics::Color::new(1.0, 1.0, 0.0, 1.0);
+                draw_centered_text(ctx, &label, text_size, y, yellow)?;
+            } else {
+                let label = self.action_label(action);
+                draw_centered_text(ctx, &label, text_size, y, graphics::WHITE)?;
+            }
+        }
+
+        let hint = match scene {
+            Scene::Options => {
+                Some("Left/Right to change, rule changes apply from the next match".to_string())
+            }
+            Scene::Controls => self.controls_message.clone(),
+            Scene::Connecting => match (&self.net, &self.dedicated) {
+                (Some(net), _) => match net.status() {
+                    netplay::Status::Waiting(port) => {
+                        Some(format!("Waiting for another player on port {}", port))
+                    }
+                    netplay::Status::Connecting(host) => Some(format!("Connecting to {}", host)),
+                    _ => None,
+                },
+                (_, Some(connection)) => match connection.status() {
+                    remote::Status::Connecting(server) => {
+                        Some(format!("Connecting to the server at {}", server))
+                    }
+                    _ if connection.spectating => {
+                        Some("Waiting for the match to start".to_string())
+                    }
+                    _ => Some("Waiting for an opponent".to_string()),
+                },
+                _ => None,
+            },
+            Scene::Lobby => match &self.lobby {
+                Some(lobby) if lobby.games().is_empty() => {
+                    Some("Looking for games on the local network...".to_string())
+                }
+                _ => Some("Pick a game to join it, or to watch it if it's full".to_string()),
+            },
+            _ => None,
+        };
+        if let Some(hint) = hint {
+            draw_centered_text(ctx, &hint, 16.0, screen_h - 30.0, graphics::WHITE)?;
+        }
+        Ok(())
+    }
+
+    /// Remakes everything that has to be as big as the window
+    fn fit_to_window(&mut self, ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        // a minimized window has no size, keep what we had until it comes back
+        if width < 1.0 || height < 1.0 {
+            return Ok(());
+        }
+        if let Some(lighting) = &mut self.lighting {
+            lighting.resize(ctx, width, height)?;
+        }
+        if let Some(post_processing) = &mut self.post_processing {
+            post_processing.resize(ctx, width, height)?;
+        }
+        if !self.graphics.fullscreen {
+            self.graphics.width = width;
+            self.graphics.height = height;
         }
         Ok(())
     }
+
+    /// Writes the window size, fullscreen, lighting and who plays with the mouse to the
+    /// settings file if the player changed them, so the next run starts the same way. Only what was
+    /// changed in the game gets written, not what the command line asked for.
+    fn save_settings(&mut self) {
+        let (now, started) = (&self.graphics, &self.started_graphics);
+        let saved = &mut self.settings.graphics;
+        let mut changed = false;
+        if (now.width, now.height) != (started.width, started.height) {
+            saved.width = now.width;
+            saved.height = now.height;
+            changed = true;
+        }
+        if now.fullscreen != started.fullscreen {
+            saved.fullscreen = now.fullscreen;
+            changed = true;
+        }
+        if now.lighting != started.lighting {
+            saved.lighting = now.lighting;
+            changed = true;
+        }
+        for side in 0..2 {
+            let mouse = self.controller_kinds[side] == ControllerKind::Mouse;
+            let saved = self.settings.players.side_mut(side);
+            if mouse != saved.mouse {
+                saved.mouse = mouse;
+                changed = true;
+            }
+            // against the speed the game started with, a bad one in the file isn't a change
+            if self.mouse_speeds[side] != saved.mouse_speed() {
+                saved.mouse_speed = self.mouse_speeds[side];
+                changed = true;
+            }
+        }
+        if changed {
+            self.settings.save();
+        }
+    }
+
+    fn toggle_fullscreen(&mut self, ctx: &mut Context) {
+        let fullscreen_type = if self.graphics.fullscreen {
+            conf::FullscreenType::Windowed
+        } else {
+            conf::FullscreenType::Desktop
+        };
+        match graphics::set_fullscreen(ctx, fullscreen_type) {
+            Ok(()) => self.graphics.fullscreen = !self.graphics.fullscreen,
+            Err(e) => self.show_notice(format!("Could not switch to fullscreen: {}", e)),
+        }
+    }
+
+    /// Sets up the effects in the `[post_processing]` settings. If the graphics
+    /// card can't compile the shaders the game is drawn without them instead.
+    fn set_post_processing(&mut self, ctx: &mut Context) {
+        self.post_processing = None;
+        let settings = &self.settings.post_processing;
+        if !settings.enabled() {
+            return;
+        }
+        let (window_w, window_h) = graphics::drawable_size(ctx);
+        match PostProcess::new(ctx, settings, window_w, window_h) {
+            Ok(post_processing) => self.post_processing = Some(post_processing),
+            Err(e) => {
+                eprintln!(
+                    "could not set up post-processing, drawing without it: {}",
+                    e
+                );
+                self.show_notice("Post-processing doesn't work on this graphics card".to_string());
+            }
+        }
+    }
+
+    /// Switches the light and shadows to `quality`. If the graphics card can't
+    /// compile the shaders the game is drawn without them instead.
+    fn set_lighting(&mut self, ctx: &mut Context, quality: Quality) {
+        self.graphics.lighting = quality;
+        self.lighting = None;
+        if quality == Quality::Off {
+            return;
+        }
+        let (window_w, window_h) = graphics::drawable_size(ctx);
+        let rays = self.graphics.light_rays();
+        let max_lights = self.graphics.max_lights();
+        match Lighting::new(ctx, quality, rays, max_lights, window_w, window_h) {
+            Ok(lighting) => self.lighting = Some(lighting),
+            Err(e) => {
+                eprintln!("could not set up the lighting, drawing without it: {}", e);
+                self.show_notice("Lighting doesn't work on this graphics card".to_string());
+            }
+        }
+    }
+
+    /// Swaps in the light shaders from resources after one of them was saved
+    fn reload_shaders(&mut self, ctx: &mut Context) {
+        if self.lighting.is_none() && self.post_processing.is_none() {
+            return;
+        }
+        let mut errors = Vec::new();
+        if let Some(lighting) = &mut self.lighting {
+            if let Err(e) = lighting.reload_shaders(ctx) {
+                errors.push(e.to_string());
+            }
+        }
+        if let Some(post_processing) = &mut self.post_processing {
+            if let Err(e) = post_processing.reload_shaders(ctx) {
+                errors.push(e.to_string());
+            }
+        }
+        if errors.is_empty() {
+            self.shader_error = None;
+            self.show_notice("Shaders reloaded".to_string());
+        } else {
+            self.shader_error = Some(errors.join("\n"));
+        }
+    }
+
+    /// The lights to draw this frame, the ball's first and then the newest flashes,
+    /// the racket glows and the newest sparks for as long as there's room
+    fn frame_lights(
+        &self,
+        ctx: &Context,
+        player_1_pos: na::Point2<f32>,
+        player_2_pos: na::Point2<f32>,
+    ) -> Vec<Light> {
+        let mut lights = vec![self.torch];
+        for flash in self.flashes.iter().rev() {
+            lights.push(Light {
+                pos: light_pos(ctx, flash.pos),
+                color: flash.color,
+                strength: flash.strength_now(),
+                glow: 0.0,
+            });
+        }
+        let in_front = RACKET_WIDTH_HALF + RACKET_LIGHT_OFFSET;
+        let rackets = [
+            (Player::One, player_1_pos + na::Vector2::new(in_front, 0.0)),
+            (Player::Two, player_2_pos - na::Vector2::new(in_front, 0.0)),
+        ];
+        for &(player, pos) in &rackets {
+            lights.push(Light {
+                pos: light_pos(ctx, pos),
+                color: player_color(player),
+                strength: RACKET_LIGHT_STRENGTH,
+                glow: 0.0,
+            });
+        }
+        if self.graphics.particle_lights {
+            let room = self.graphics.max_lights().saturating_sub(lights.len());
+            for (pos, color, life_left) in self.particles.lights().take(room) {
+                lights.push(Light {
+                    pos: light_pos(ctx, pos),
+                    color,
+                    strength: PARTICLE_LIGHT_STRENGTH * life_left,
+                    glow: 0.0,
+                });
+            }
+        }
+        lights.truncate(self.graphics.max_lights());
+        lights
+    }
+
+    /// The rackets and the score, the things that cast shadows
+    fn draw_foreground(
+        &self,
+        ctx: &mut Context,
+        player_1_pos: na::Point2<f32>,
+        player_2_pos: na::Point2<f32>,
+    ) -> GameResult {
+        graphics::draw(
+            ctx,
+            &self.racket_mesh,
+            DrawParam::new().dest(Point2::new(player_1_pos.x, player_1_pos.y)),
+        )?;
+
+        graphics::draw(
+            ctx,
+            &self.racket_mesh_2,
+            DrawParam::new().dest(Point2::new(player_2_pos.x, player_2_pos.y)),
+        )?;
+
+        let score_text = if self.sim.rules.best_of > 1 {
+            graphics::Text::new(format!(
+                "({})  {}        {}  ({})",
+                self.sim.games[0], self.sim.scores[0], self.sim.scores[1], self.sim.games[1]
+            ))
+        } else {
+            graphics::Text::new(format!(
+                "{}        {}",
+                self.sim.scores[0], self.sim.scores[1]
+            ))
+        };
+
+        let mut score_pos = na::Point2::new(self.sim.field_w * 0.5, 20.0);
+        let (score_text_w, score_text_h) = score_text.dimensions(ctx);
+        score_pos -= na::Vector2::new(score_text_w as f32 * 0.5, score_text_h as f32 * 0.5);
+
+        graphics::draw(
+            ctx,
+            &score_text,
+            DrawParam::new().dest(Point2::new(score_pos.x, score_pos.y)),
+        )
+    }
 }
 
 impl event::EventHandler for MainState {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
-        let dt = timer::delta(ctx).as_secs_f32();
-        let (screen_w, screen_h) = graphics::drawable_size(ctx);
-
-        move_racket(&mut self.player_1_pos, KeyCode::W, 1.0, ctx);
-        move_racket(&mut self.player_1_pos, KeyCode::S, -1.0, ctx);
-        move_racket(&mut self.player_2_pos, KeyCode::Up, 1.0, ctx);
-        move_racket(&mut self.player_2_pos, KeyCode::Down, -1.0, ctx);
-
-        self.ball_pos += self.ball_vel * dt;
-
-        if self.ball_pos.x < 0.0 {
-            self.ball_pos.x = screen_w * 0.5;
-            self.ball_pos.y = screen_h * 0.5;
-            randomize_vec(&mut self.ball_vel, BALL_SPEED, BALL_SPEED);
-            self.player_2_score += 1;
-        }
-        if self.ball_pos.x > screen_w {
-            self.ball_pos.x = screen_w * 0.5;
-            self.ball_pos.y = screen_h * 0.5;
-            randomize_vec(&mut self.ball_vel, BALL_SPEED, BALL_SPEED);
-            self.player_1_score += 1;
-        }
-        if self.ball_pos.y < BALL_SIZE_HALF {
-            self.ball_pos.y = BALL_SIZE_HALF;
-            self.ball_vel.y = self.ball_vel.y.abs();
-        } else if self.ball_pos.y > screen_h - BALL_SIZE_HALF {
-            self.ball_pos.y = screen_h - BALL_SIZE_HALF;
-            self.ball_vel.y = -self.ball_vel.y.abs();
-        }
-
-        //comment the following lines to disable "AI"
-        if self.ball_pos.y < self.player_2_pos.y {
-            self.player_2_pos.y -= PLAYER_SPEED * 0.4 * dt;
-        } else {
-            self.player_2_pos.y += PLAYER_SPEED * 0.4 * dt;
+        // the game logic always runs with the same dt, no matter the framerate,
+        // so a slow frame just means we do a couple of ticks in a row
+        let dt = 1.0 / self.tick_rate as f32;
+
+        for side in self.gamepads.drop_disconnected(ctx) {
+            self.show_notice(format!("Gamepad for player {} disconnected", side + 1));
+            // don't let the ball fly past someone whose controller just died
+            if self.scenes.top() == Scene::Playing {
+                self.scenes.push(Scene::Paused);
+            }
+        }
+        if let Some((_, time_left)) = &mut self.notice {
+            *time_left -= timer::delta(ctx).as_secs_f32();
+            if *time_left <= 0.0 {
+                self.notice = None;
+            }
+        }
+        if self
+            .shader_watcher
+            .as_mut()
+            .is_some_and(shaders::Watcher::changed)
+        {
+            self.reload_shaders(ctx);
         }
-        clamp(
-            &mut self.player_2_pos.y,
-            RACKET_HEIGHT_HALF,
-            screen_h - RACKET_HEIGHT_HALF,
-        );
 
-        let intersects_player_1 = self.ball_pos.x - BALL_SIZE_HALF
-            < self.player_1_pos.x + RACKET_WIDTH_HALF
-            && self.ball_pos.x + BALL_SIZE_HALF > self.player_1_pos.x - RACKET_WIDTH_HALF
-            && self.ball_pos.y - BALL_SIZE_HALF < self.player_1_pos.y + RACKET_HEIGHT_HALF
-            && self.ball_pos.y + BALL_SIZE_HALF > self.player_1_pos.y - RACKET_HEIGHT_HALF;
-
-        if intersects_player_1 {
-            self.ball_pos.x = RACKET_WIDTH * 2.0 + PADDING;
-            self.ball_vel.x = self.ball_vel.x.abs() + 30.0;
-            //change color of ball
-            self.torch.light_color = [0.0, 0.0, 1.0, 1.0];
-            self.ball_mesh = graphics::Mesh::new_circle(
-                ctx,
-                graphics::DrawMode::fill(),
-                Point2::new(-BALL_SIZE_HALF, -BALL_SIZE_HALF),
-                BALL_SIZE,
-                0.1,
-                graphics::Color::new(0.0, 0.0, 1.0, 1.0),
-            )?;
+        self.update_online();
+        // an online match keeps going behind the menus, the other player is still playing
+        let local_player = self.local_player();
+        let spectating = self.spectating();
+        if self.scenes.top() != Scene::Playing && local_player.is_none() && !spectating {
+            // throw away the time spent in menus, or the game would try to catch up on it
+            while timer::check_update_time(ctx, self.tick_rate) {}
         }
-        let intersects_player_2 = self.ball_pos.x - BALL_SIZE_HALF
-            < self.player_2_pos.x + RACKET_WIDTH_HALF
-            && self.ball_pos.x + BALL_SIZE_HALF > self.player_2_pos.x - RACKET_WIDTH_HALF
-            && self.ball_pos.y - BALL_SIZE_HALF < self.player_2_pos.y + RACKET_HEIGHT_HALF
-            && self.ball_pos.y + BALL_SIZE_HALF > self.player_2_pos.y - RACKET_HEIGHT_HALF;
-
-        if intersects_player_2 {
-            self.ball_pos.x = screen_w - RACKET_WIDTH * 2.0 - PADDING;
-            self.ball_vel.x = -self.ball_vel.x.abs() - 30.0;
-            //change color of ball
-            self.torch.light_color = [1.0, 0.0, 0.0, 1.0];
-            self.ball_mesh = graphics::Mesh::new_circle(
-                ctx,
-                graphics::DrawMode::fill(),
-                Point2::new(-BALL_SIZE_HALF, -BALL_SIZE_HALF),
-                BALL_SIZE,
-                0.1,
-                graphics::Color::new(1.0, 0.0, 0.0, 1.0),
-            )?;
+
+        while timer::check_update_time(ctx, self.tick_rate) {
+            let playing = self.scenes.top() == Scene::Playing;
+            let before = self.sim.clone();
+
+            // flashes and particles go along with the match, so they hold still
+            // while it's stopped in the menus or a paused replay
+            let effects_dt = match &self.playback {
+                Some(playback) if playback.paused || playback.finished() => 0.0,
+                Some(playback) => dt * playback.speed,
+                None => dt,
+            };
+            for flash in &mut self.flashes {
+                flash.update(effects_dt);
+            }
+            self.flashes.retain(|flash| !flash.faded());
+            self.particles.update(effects_dt);
+
+            let spectated = self.dedicated.as_mut().filter(|c| c.spectating);
+            let events = if let Some(playback) = &mut self.playback {
+                match playback.advance(&mut self.sim) {
+                    Some(events) => events,
+                    None => continue,
+                }
+            } else if let Some(connection) = spectated {
+                // spectators only ever see snapshots, there's nothing of ours to send
+                match connection.apply(&mut self.sim) {
+                    Some(events) => events,
+                    None => continue,
+                }
+            } else if let Some(player) = local_player {
+                // online we only have one racket, so it gets player 1's controls whichever side it's on
+                let mut local = 0.0;
+                if playing {
+                    let held = self.held_input(ctx, 0, player, dt);
+                    local = self.controllers[0].think(&self.sim, player, held, dt);
+                }
+                if let Some(net) = &mut self.net {
+                    match net.advance(&mut self.sim, local, dt) {
+                        Some(events) => events,
+                        None => continue,
+                    }
+                } else if let Some(connection) = &mut self.dedicated {
+                    connection.send_input(local);
+                    // snapshots don't come every tick, the field stays as the last one left it until then
+                    match connection.apply(&mut self.sim) {
+                        Some(events) => events,
+                        None => continue,
+                    }
+                } else {
+                    Vec::new()
+                }
+            } else {
+                let held_1 = self.held_input(ctx, 0, Player::One, dt);
+                let held_2 = self.held_input(ctx, 1, Player::Two, dt);
+                let input = Input {
+                    player_1: self.controllers[0].think(&self.sim, Player::One, held_1, dt),
+                    player_2: self.controllers[1].think(&self.sim, Player::Two, held_2, dt),
+                };
+                if let Some(recording) = &mut self.recording {
+                    recording.record(input);
+                }
+                self.sim.step(input, dt)
+            };
+            self.prev_sim = before;
+
+            for event in events {
+                match event {
+                    //change color of ball
+                    Event::Hit(player) => {
+                        self.set_ball_color(ctx, Some(player))?;
+                        let ball =
+                            self.sim.ball_pos - na::Vector2::new(BALL_SIZE_HALF, BALL_SIZE_HALF);
+                        let color = player_color(player);
+                        self.flashes.push(Flash::new(
+                            ball,
+                            color,
+                            HIT_FLASH_STRENGTH,
+                            HIT_FLASH_TIME,
+                        ));
+                        // sparks fly back the way the ball is going now
+                        let direction = match player {
+                            Player::One => 0.0,
+                            Player::Two => PI,
+                        };
+                        self.particles
+                            .emit(&particles::SPARKS, ball, direction, color);
+                    }
+                    Event::Wall => {
+                        let ball =
+                            self.sim.ball_pos - na::Vector2::new(BALL_SIZE_HALF, BALL_SIZE_HALF);
+                        // up off the bottom wall, down off the top one
+                        let direction = if ball.y > self.sim.field_h * 0.5 {
+                            -PI * 0.5
+                        } else {
+                            PI * 0.5
+                        };
+                        self.particles
+                            .emit(&particles::DUST, ball, direction, DUST_COLOR);
+                    }
+                    Event::Goal(scorer) => {
+                        // light up the edge the ball went out of, in the scorer's color
+                        let x = match scorer {
+                            Player::One => self.sim.field_w - 1.0,
+                            Player::Two => 1.0,
+                        };
+                        let goal = na::Point2::new(x, self.prev_sim.ball_pos.y);
+                        let color = player_color(scorer);
+                        self.flashes.push(Flash::new(
+                            goal,
+                            color,
+                            GOAL_FLASH_STRENGTH,
+                            GOAL_FLASH_TIME,
+                        ));
+                        self.particles.emit(&particles::BURST, goal, 0.0, color);
+                        // the ball teleported back to the middle,
+                        // so don't draw it sliding across the field
+                        self.prev_sim = self.sim.clone();
+                    }
+                    Event::MatchWon(_) => {
+                        // an online match is saved when we leave it, once both sides agree on the ending
+                        if self.net.is_none() {
+                            self.finish_recording();
+                        }
+                        // a server keeps its seed to itself, and a replay is a match that's already been saved
+                        if self.dedicated.is_none() && self.playback.is_none() {
+                            if let Err(e) = results::record(&self.sim) {
+                                eprintln!("could not save the match result: {}", e);
+                            }
+                        }
+                        self.scenes.push(Scene::GameOver);
+                    }
+                    _ => (),
+                }
+            }
         }
 
         self.torch.glow = LIGHT_GLOW_FACTOR * ((timer::ticks(ctx) as f32) / LIGHT_GLOW_RATE).cos();
 
-        // change the light to follow the ball
-        // It took quite a bit of thinking to get this sorted
-        // the light has a f32 value between 0 and 1
-        // AND it's origin is the lower left corner instead of the
-        // upper right
-        let torch_x = (self.ball_pos.x - BALL_SIZE_HALF) / screen_w;
-        let torch_y = 0.5 + 0.5 - ((self.ball_pos.y - BALL_SIZE_HALF) / screen_h);
-        self.torch.pos = [torch_x, torch_y];
-
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        let screen_w = graphics::drawable_size(ctx).0;
+        let (field_w, field_h) = (self.sim.field_w, self.sim.field_h);
+        let (window_w, window_h) = graphics::drawable_size(ctx);
+        let screen = letterbox(window_w, window_h, field_w, field_h);
+        graphics::set_screen_coordinates(ctx, screen)?;
+
+        // how far we are between the previous tick and the current one
+        let tick_dt = 1.0 / self.tick_rate as f32;
+        let alpha = if self.scenes.top() == Scene::Playing {
+            (timer::remaining_update_time(ctx).as_secs_f32() / tick_dt).min(1.0)
+        } else {
+            1.0
+        };
+        let player_1_pos = lerp(self.prev_sim.player_1_pos, self.sim.player_1_pos, alpha);
+        let player_2_pos = lerp(self.prev_sim.player_2_pos, self.sim.player_2_pos, alpha);
+        let ball_pos = lerp(self.prev_sim.ball_pos, self.sim.ball_pos, alpha);
 
-        let origin = DrawParam::new()
-            .dest(Point2::new(0.0, 0.0))
-            .scale(Vector2::new(0.5, 0.5));
-        let canvas_origin = DrawParam::new();
+        // change the light to follow the ball
+        // It took quite a bit of thinking to get this sorted
+        // the light has a f32 value between 0 and 1
+        // AND it's origin is the lower left corner instead of the
+        // upper right
+        let ball_center = ball_pos - na::Vector2::new(BALL_SIZE_HALF, BALL_SIZE_HALF);
+        self.torch.pos = light_pos(ctx, ball_center);
+        self.torch.strength = LIGHT_STRENGTH;
+        // spectators can hang a brighter light over the middle to see the whole field at once
+        if self.overview && self.spectating() {
+            self.torch.pos = light_pos(ctx, na::Point2::new(field_w * 0.5, field_h * 0.5));
+            self.torch.strength = OVERVIEW_LIGHT_STRENGTH;
+        }
 
         // First thing we want to do it to render all the foreground items (that
         // will have shadows) onto their own Canvas (off-screen render). We will
         // use this canvas to:
         //  - run the occlusions shader to determine where the shadows are
         //  - render to screen once all the shadows are calculated and rendered
-        {
-            graphics::set_canvas(ctx, Some(&self.foreground));
-            graphics::clear(ctx, graphics::Color::new(0.0, 0.0, 0.0, 0.0));
-
-            graphics::draw(
-                ctx,
-                &self.racket_mesh,
-                DrawParam::new().dest(Point2::new(self.player_1_pos.x, self.player_1_pos.y)),
-            )?;
-
-            graphics::draw(
-                ctx,
-                &self.racket_mesh_2,
-                DrawParam::new().dest(Point2::new(self.player_2_pos.x, self.player_2_pos.y)),
-            )?;
-
-            let score_text = graphics::Text::new(format!(
-                "{}        {}",
-                self.player_1_score, self.player_2_score
-            ));
-
-            let mut score_pos = na::Point2::new(screen_w * 0.5, 20.0);
-            let (score_text_w, score_text_h) = score_text.dimensions(ctx);
-            score_pos -= na::Vector2::new(score_text_w as f32 * 0.5, score_text_h as f32 * 0.5);
-
-            graphics::draw(
-                ctx,
-                &score_text,
-                DrawParam::new().dest(Point2::new(score_pos.x, score_pos.y)),
-            )?;
+        if let Some(lighting) = &self.lighting {
+            lighting.begin(ctx);
+            self.draw_foreground(ctx, player_1_pos, player_2_pos)?;
         }
 
         // Then we draw our light and shadow maps
-        {
-            let torch = self.torch;
-
-            graphics::set_canvas(ctx, Some(&self.lights));
-            graphics::clear(ctx, graphics::Color::new(0.0, 0.0, 0.0, 1.0));
-
-            graphics::set_canvas(ctx, Some(&self.shadows));
-            graphics::clear(ctx, graphics::Color::new(0.0, 0.0, 0.0, 1.0));
-            self.render_light(ctx, torch, origin, canvas_origin)?;
+        if self.lighting.is_some() {
+            let mut lights = self.frame_lights(ctx, player_1_pos, player_2_pos);
+            // the shaders measure light in window heights, with black bars above
+            // and below the field that would make it reach further across the field
+            let zoom = (field_h / screen.h).powi(2);
+            for light in &mut lights {
+                light.strength *= zoom;
+                light.glow *= zoom;
+            }
+            if let Some(lighting) = &mut self.lighting {
+                lighting.render(ctx, &lights)?;
+            }
         }
 
         // Now lets finally render to screen starting with out background, then
         // the shadows and lights overtop and finally our foreground.
-        graphics::set_canvas(ctx, None);
+        // Without lighting the foreground just goes straight on top of the background.
+        // With post-processing all of it goes onto its canvas first.
+        match &self.post_processing {
+            Some(post_processing) => post_processing.begin(ctx),
+            None => graphics::set_canvas(ctx, None),
+        }
         graphics::clear(ctx, graphics::WHITE);
         graphics::draw(ctx, &self.background, DrawParam::default())?;
-        graphics::draw(ctx, &self.shadows, DrawParam::default())?;
-        graphics::draw(ctx, &self.foreground, DrawParam::default())?;
-        graphics::draw(ctx, &self.lights, DrawParam::default())?;
+        match &self.lighting {
+            Some(lighting) => lighting.draw(ctx)?,
+            None => self.draw_foreground(ctx, player_1_pos, player_2_pos)?,
+        }
+
+        // black out whatever the window shows beyond the field, a bit past the
+        // edges of the window so rounding never leaves a gap
+        let bars = [
+            graphics::Rect::new(
+                screen.x - 1.0,
+                screen.y - 1.0,
+                -screen.x + 1.0,
+                screen.h + 2.0,
+            ),
+            graphics::Rect::new(
+                field_w,
+                screen.y - 1.0,
+                screen.right() - field_w + 1.0,
+                screen.h + 2.0,
+            ),
+            graphics::Rect::new(
+                screen.x - 1.0,
+                screen.y - 1.0,
+                screen.w + 2.0,
+                -screen.y + 1.0,
+            ),
+            graphics::Rect::new(
+                screen.x - 1.0,
+                field_h,
+                screen.w + 2.0,
+                screen.bottom() - field_h + 1.0,
+            ),
+        ];
+        for bar in &bars {
+            let param = DrawParam::new()
+                .dest(bar.point())
+                .scale(Vector2::new(bar.w, bar.h))
+                .color(graphics::BLACK);
+            graphics::draw(ctx, &self.square_mesh, param)?;
+        }
 
         //we dont want the middle line or the ball to be counted as objects for the light
         // so we render them last
-        let screen_middle_x = graphics::drawable_size(ctx).0 * 0.5;
         graphics::draw(
             ctx,
-            &self.middle_mesh,
-            DrawParam::new().dest(Point2::new(screen_middle_x, 0.0)),
+            &self.square_mesh,
+            DrawParam::new()
+                .dest(Point2::new((field_w - MIDDLE_LINE_W) * 0.5, 0.0))
+                .scale(Vector2::new(MIDDLE_LINE_W, field_h)),
         )?;
         graphics::draw(
             ctx,
             &self.ball_mesh,
-            DrawParam::new().dest(Point2::new(self.ball_pos.x, self.ball_pos.y)),
+            DrawParam::new().dest(Point2::new(ball_pos.x, ball_pos.y)),
         )?;
+        self.particles.draw(ctx)?;
+        // the effects go over the game but not the text on top
+        if let Some(post_processing) = &self.post_processing {
+            let light_map = self.lighting.as_ref().map(Lighting::light_map);
+            post_processing.finish(ctx, light_map)?;
+        }
+
+        if self.scenes.top() == Scene::Playing && self.sim.serve_timer > 0.0 {
+            let countdown = self.sim.serve_timer.ceil().to_string();
+            draw_centered_text(ctx, &countdown, 48.0, field_h * 0.4, graphics::WHITE)?;
+        }
+        if let Some(frame) = self.net.as_ref().and_then(NetSession::desync) {
+            let warning = format!("Out of sync with the other player since tick {}", frame);
+            let red = graphics::Color::new(1.0, 0.3, 0.3, 1.0);
+            draw_centered_text(ctx, &warning, 16.0, 50.0, red)?;
+        }
+        if let (Some(playback), Scene::Playing) = (&self.playback, self.scenes.top()) {
+            let state = if playback.paused {
+                ", paused".to_string()
+            } else if playback.finished() {
+                ", finished".to_string()
+            } else if playback.speed != 1.0 {
+                format!(" at {}x", playback.speed)
+            } else {
+                String::new()
+            };
+            let status = format!(
+                "Replay {} / {}{}",
+                format_time(playback.time()),
+                format_time(playback.duration()),
+                state
+            );
+            let help = format!(
+                "{} pause, {}/{} skip {}s, {}/{} speed",
+                self.key_hint(Control::Confirm),
+                self.key_hint(Control::MenuLeft),
+                self.key_hint(Control::MenuRight),
+                REPLAY_SKIP,
+                self.key_hint(Control::MenuUp),
+                self.key_hint(Control::MenuDown)
+            );
+            let grey = graphics::Color::new(0.7, 0.7, 0.7, 1.0);
+            draw_centered_text(ctx, &status, 16.0, field_h - 40.0, graphics::WHITE)?;
+            draw_centered_text(ctx, &help, 14.0, field_h - 20.0, grey)?;
+        }
+        if self.debug {
+            self.draw_debug(ctx)?;
+        }
+        let spectators = self.spectators();
+        if self.scenes.top() == Scene::Playing && spectators > 0 {
+            let watching = format!("{} watching", spectators);
+            let grey = graphics::Color::new(0.7, 0.7, 0.7, 1.0);
+            draw_centered_text(ctx, &watching, 16.0, field_h - 20.0, grey)?;
+        }
+        self.draw_menu(ctx)?;
+        if let Some((notice, _)) = &self.notice {
+            draw_centered_text(ctx, notice, 16.0, field_h - 60.0, graphics::WHITE)?;
+        }
+        if let Some(error) = &self.shader_error {
+            let mut text = graphics::Text::new(
+                graphics::TextFragment::new(error.as_str()).scale(graphics::Scale::uniform(14.0)),
+            );
+            text.set_bounds(
+                Point2::new(field_w - 20.0, f32::INFINITY),
+                graphics::Align::Left,
+            );
+            let red = graphics::Color::new(1.0, 0.3, 0.3, 1.0);
+            graphics::draw(
+                ctx,
+                &text,
+                DrawParam::new().dest(Point2::new(10.0, 10.0)).color(red),
+            )?;
+        }
+
         graphics::present(ctx)?;
         Ok(())
     }
+
+    fn key_down_event(
+        &mut self,
+        ctx: &mut Context,
+        keycode: KeyCode,
+        _keymods: KeyMods,
+        _repeat: bool,
+    ) {
+        // the next key after picking a control on the controls screen gets bound to it,
+        // Escape is hardwired to cancel so you can't lock yourself out
+        if let Some(control) = self.rebinding.take() {
+            if keycode == KeyCode::Escape {
+                return;
+            }
+            match self.controls.rebind(control, keycode) {
+                Ok(()) => self.controls.save(),
+                Err(other) => {
+                    self.controls_message = Some(format!(
+                        "{} is already used for {}",
+                        key_name(keycode),
+                        other.name()
+                    ))
+                }
+            }
+            return;
+        }
+
+        let pressed: Vec<Control> = Control::ALL
+            .iter()
+            .copied()
+            .filter(|&control| self.controls.matches(control, keycode))
+            .collect();
+        self.handle_controls(ctx, &pressed);
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if let Err(e) = self.fit_to_window(ctx, width, height) {
+            eprintln!("could not resize the window: {}", e);
+        }
+    }
+
+    fn gamepad_button_down_event(&mut self, ctx: &mut Context, button: Button, id: GamepadId) {
+        // any button on a new gamepad grabs the first free side
+        if let Some(side) = self.gamepads.join(id) {
+            self.show_notice(format!(
+                "Gamepad joined as player {}, press Select to switch sides",
+                side + 1
+            ));
+            return;
+        }
+        if button == Button::Select {
+            if let Some(side) = self.gamepads.switch_side(id) {
+                self.show_notice(format!("Gamepad switched to player {}", side + 1));
+            }
+            return;
+        }
+        // gamepads can't rebind keys, and shouldn't move the menu while a key is awaited
+        if self.rebinding.is_none() {
+            self.handle_controls(ctx, gamepads::button_controls(button));
+        }
+    }
 }
 
 fn main() -> GameResult {
@@ -597,9 +1705,18 @@ fn main() -> GameResult {
         path::PathBuf::from("./resources")
     };
 
-    let cb = ggez::ContextBuilder::new("shadows_test", "halvard").add_resource_path(resource_dir);
+    let args = Args::parse();
+    let settings = Settings::load();
+    let window = settings.graphics.with_args(&args);
+
+    let cb = ggez::ContextBuilder::new("shadows_test", "halvard")
+        .window_setup(window.window_setup())
+        .window_mode(window.window_mode())
+        .add_resource_path(&resource_dir);
     let (ctx, event_loop) = &mut cb.build()?;
 
-    let state = &mut MainState::new(ctx)?;
-    event::run(ctx, event_loop, state)
+    let state = &mut MainState::new(ctx, &args, settings, &resource_dir)?;
+    let result = event::run(ctx, event_loop, state);
+    state.save_settings();
+    result
 }
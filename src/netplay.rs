@@ -0,0 +1,754 @@
+//! Online two player over UDP.
+//! Both peers run the whole simulation and only send each other their inputs.
+//! Inputs from the other side that haven't arrived yet are guessed, and when the
+//! real ones turn up and the guess was wrong the game is rolled back to the last
+//! state both sides agree on and played forward again, like GGPO does it.
+//!
+//! The host also lets spectators watch, sending them snapshots the same way a
+//! `pong-server` does, but only of ticks that can't be rolled back any more.
+
+use pong::protocol::{ClientMessage, ServerMessage, Snapshot};
+use pong::replay::Replay;
+use pong::rules::Ruleset;
+use pong::simulation::{check_tick_rate, clamp, Event, Input, Player, Simulation, TICK_RATE};
+use pong::spectators::Spectators;
+use pong::wire::{Reader, Writer};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Starts every packet so stray traffic on the port gets ignored
+const MAGIC: &[u8; 4] = b"RPNG";
+/// Bumped whenever the packets change, so different builds don't try to play each other
+const PROTOCOL_VERSION: u8 = 3;
+/// Ticks between pressing a key and it taking effect. This gives the input a
+/// head start over the network, so there is less to roll back.
+const INPUT_DELAY: u32 = 2;
+/// How many ticks we may run ahead of the other side's inputs before waiting for them
+const MAX_PREDICTION: u32 = 8;
+/// Inputs the other side hasn't acknowledged yet get resent in every packet, up to this many
+const MAX_INPUTS_PER_PACKET: usize = 32;
+/// How far from our tick the other side's inputs can be. It can only get so far
+/// ahead before it waits for us, anything further off is garbage.
+const INPUT_WINDOW: u32 = MAX_PREDICTION + INPUT_DELAY * 2 + MAX_INPUTS_PER_PACKET as u32;
+/// How often (in ticks) the two sides compare checksums
+const CHECKSUM_INTERVAL: u32 = 60;
+/// How long we wait to hear from the other side before giving up on it,
+/// or for the host to answer at all when joining
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the joining side knocks on the host's door
+const HELLO_INTERVAL: Duration = Duration::from_millis(250);
+/// How many ticks apart we'll wait a tick to let a slower peer catch up
+const SYNC_INTERVAL: u32 = 30;
+/// Spectators get a snapshot every this many ticks
+const SPECTATOR_INTERVAL: u32 = 2;
+const MAX_PACKET_SIZE: usize = 1024;
+
+enum Message {
+    /// Sent by the joining side until the host answers
+    Hello,
+    /// Everything the joining side needs to start the exact same match
+    Welcome {
+        seed: u64,
+        field_w: f32,
+        field_h: f32,
+        rules: Ruleset,
+        tick_rate: u32,
+    },
+    Inputs {
+        /// The tick the sender is on
+        frame: u32,
+        /// How many ticks the sender thinks it is ahead of us
+        advantage: i32,
+        /// The sender has all our inputs before this tick
+        ack: u32,
+        /// The tick of the first input
+        start: u32,
+        inputs: Vec<f32>,
+        /// The sender's checksum for a tick, once all the inputs up to it are known
+        checksum: Option<(u32, u64)>,
+    },
+    /// The other side left
+    Bye,
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.bytes(MAGIC);
+        w.u8(PROTOCOL_VERSION);
+        match self {
+            Message::Hello => w.u8(0),
+            Message::Welcome {
+                seed,
+                field_w,
+                field_h,
+                rules,
+                tick_rate,
+            } => {
+                w.u8(1);
+                w.u64(*seed);
+                w.f32(*field_w);
+                w.f32(*field_h);
+                w.rules(rules);
+                w.u32(*tick_rate);
+            }
+            Message::Inputs {
+                frame,
+                advantage,
+                ack,
+                start,
+                inputs,
+                checksum,
+            } => {
+                w.u8(2);
+                w.u32(*frame);
+                w.i32(*advantage);
+                w.u32(*ack);
+                w.u32(*start);
+                w.u8(inputs.len() as u8);
+                for &input in inputs {
+                    w.f32(input);
+                }
+                w.bool(checksum.is_some());
+                if let Some((frame, checksum)) = checksum {
+                    w.u32(*frame);
+                    w.u64(*checksum);
+                }
+            }
+            Message::Bye => w.u8(3),
+        }
+        w.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Message> {
+        let mut r = Reader::new(bytes);
+        if r.bytes(MAGIC.len())? != MAGIC || r.u8()? != PROTOCOL_VERSION {
+            return None;
+        }
+        match r.u8()? {
+            0 => Some(Message::Hello),
+            1 => Some(Message::Welcome {
+                seed: r.u64()?,
+                field_w: r.f32()?,
+                field_h: r.f32()?,
+                rules: r.rules()?,
+                tick_rate: check_tick_rate(r.u32()?).ok()?,
+            }),
+            2 => {
+                let frame = r.u32()?;
+                let advantage = r.i32()?;
+                let ack = r.u32()?;
+                let start = r.u32()?;
+                let count = r.u8()?;
+                let mut inputs = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    inputs.push(r.f32()?);
+                }
+                let checksum = if r.bool()? {
+                    Some((r.u32()?, r.u64()?))
+                } else {
+                    None
+                };
+                Some(Message::Inputs {
+                    frame,
+                    advantage,
+                    ack,
+                    start,
+                    inputs,
+                    checksum,
+                })
+            }
+            3 => Some(Message::Bye),
+            _ => None,
+        }
+    }
+}
+
+/// Where the connection is at
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    /// The host is waiting for someone to join
+    Waiting(u16),
+    /// We're knocking on the host's door
+    Connecting(SocketAddr),
+    Playing,
+    /// It's over, and this is why
+    Disconnected(String),
+}
+
+pub struct NetSession {
+    socket: UdpSocket,
+    /// The other side, the host learns it from the first hello
+    peer: Option<SocketAddr>,
+    /// The racket this end of the connection controls
+    pub local_player: Player,
+    status: Status,
+    /// The match the host sends out, and the joining side gets back
+    start: Option<Simulation>,
+    /// Both sides have to tick at the same rate or they'd play different matches,
+    /// the joining side takes the host's
+    tick_rate: u32,
+    last_heard: Instant,
+    last_hello: Option<Instant>,
+    /// The tick the simulation is about to run
+    frame: u32,
+    /// The tick the first entry of each of the input lists is for. Inputs before
+    /// it can't be rolled back or asked for again, so they're forgotten.
+    first_frame: u32,
+    /// Our inputs by tick, including the ones delayed into the future
+    local_inputs: Vec<f32>,
+    /// The other side's inputs by tick, as far as they have arrived
+    remote_inputs: Vec<Option<f32>>,
+    /// The remote input each tick was actually simulated with, real or guessed
+    used_remote: Vec<f32>,
+    /// Every tick both sides agree on, kept as a replay because the inputs get forgotten
+    recording: Option<Replay>,
+    /// All the other side's inputs before this tick have arrived
+    confirmed: u32,
+    /// A guess turned out wrong, so everything from this tick has to be played again
+    rollback_from: Option<u32>,
+    /// The simulation as it was right before each of the last few ticks
+    saved: VecDeque<(u32, Simulation)>,
+    /// The other side has all our inputs before this tick
+    peer_ack: u32,
+    /// The latest tick the other side said it was on
+    peer_frame: u32,
+    peer_advantage: i32,
+    /// The next tick to take a checksum of
+    next_checksum: u32,
+    /// Our latest checksum, sent along until the next one
+    sent_checksum: Option<(u32, u64)>,
+    local_checksums: HashMap<u32, u64>,
+    remote_checksums: HashMap<u32, u64>,
+    /// Checksums for ticks before this have already been compared
+    checked_up_to: u32,
+    /// The first tick the two sides disagreed on
+    desync: Option<u32>,
+    /// The points, games and winner the game has been told about. Goals only
+    /// get told once no rollback can take them back again.
+    reported: ([u32; 2], [u32; 2], Option<Player>),
+    /// People watching the host's match
+    spectators: Spectators,
+    /// The next tick spectators get to see
+    next_spectated: u32,
+}
+
+impl NetSession {
+    fn new(socket: UdpSocket, local_player: Player, status: Status) -> io::Result<NetSession> {
+        socket.set_nonblocking(true)?;
+        Ok(NetSession {
+            socket,
+            peer: None,
+            local_player,
+            status,
+            start: None,
+            tick_rate: TICK_RATE,
+            last_heard: Instant::now(),
+            last_hello: None,
+            frame: 0,
+            first_frame: 0,
+            // the first few ticks happen before anyone could have pressed anything
+            local_inputs: vec![0.0; INPUT_DELAY as usize],
+            remote_inputs: Vec::new(),
+            used_remote: Vec::new(),
+            recording: None,
+            confirmed: 0,
+            rollback_from: None,
+            saved: VecDeque::new(),
+            peer_ack: 0,
+            peer_frame: 0,
+            peer_advantage: 0,
+            next_checksum: 0,
+            sent_checksum: None,
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            checked_up_to: 0,
+            desync: None,
+            reported: ([0, 0], [0, 0], None),
+            spectators: Spectators::new(),
+            next_spectated: 0,
+        })
+    }
+
+    /// Waits for someone to join on `port` and play `sim` against us at `tick_rate`, we are player 1
+    pub fn host(port: u16, sim: &Simulation, tick_rate: u32) -> io::Result<NetSession> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        let port = socket.local_addr()?.port();
+        let mut session = NetSession::new(socket, Player::One, Status::Waiting(port))?;
+        session.start = Some(sim.clone());
+        session.tick_rate = tick_rate;
+        session.recording = Some(Replay::new(sim, tick_rate));
+        Ok(session)
+    }
+
+    /// Joins the host at `address`, we are player 2
+    pub fn join(address: &str) -> io::Result<NetSession> {
+        let host = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address for {}", address),
+            )
+        })?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        let mut session = NetSession::new(socket, Player::Two, Status::Connecting(host))?;
+        session.peer = Some(host);
+        Ok(session)
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// How many ticks a second the match runs at, the host's once we've joined
+    pub fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    /// The first tick the two sides' simulations disagreed on, if they ever did
+    pub fn desync(&self) -> Option<u32> {
+        self.desync
+    }
+
+    /// How many people are watching, only the host lets anyone watch
+    pub fn spectators(&self) -> u8 {
+        self.spectators.count()
+    }
+
+    fn send(&self, message: &Message) {
+        if let Some(peer) = self.peer {
+            // a lost packet is no worse than a dropped one, the next one carries the same inputs
+            let _ = self.socket.send_to(&message.encode(), peer);
+        }
+    }
+
+    /// Reads everything that has arrived and keeps the connection alive.
+    /// Gives back the match to play once both sides are connected.
+    pub fn poll(&mut self) -> Option<Simulation> {
+        let mut started = None;
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let (size, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // e.g. the other side's port is closed, timing out handles that
+                Err(_) => continue,
+            };
+            let message = match Message::decode(&buffer[..size]) {
+                Some(message) => message,
+                None => {
+                    self.receive_spectator(from, &buffer[..size]);
+                    continue;
+                }
+            };
+            // until someone has joined, a hello is the only thing worth listening to
+            let from_peer = self.peer == Some(from);
+            if !from_peer && (self.peer.is_some() || !matches!(message, Message::Hello)) {
+                continue;
+            }
+            self.last_heard = Instant::now();
+            match message {
+                Message::Hello => {
+                    if let (Some(sim), Player::One) = (&self.start, self.local_player) {
+                        if self.peer.is_none() {
+                            self.peer = Some(from);
+                            self.status = Status::Playing;
+                            started = Some(sim.clone());
+                        }
+                        // answer every hello in case the welcome got lost
+                        self.send(&Message::Welcome {
+                            seed: sim.seed,
+                            field_w: sim.field_w,
+                            field_h: sim.field_h,
+                            rules: sim.rules,
+                            tick_rate: self.tick_rate,
+                        });
+                    }
+                }
+                Message::Welcome {
+                    seed,
+                    field_w,
+                    field_h,
+                    rules,
+                    tick_rate,
+                } => {
+                    if self.start.is_none() && self.local_player == Player::Two {
+                        let sim = Simulation::with_seed(field_w, field_h, rules, seed);
+                        self.start = Some(sim.clone());
+                        self.tick_rate = tick_rate;
+                        self.recording = Some(Replay::new(&sim, tick_rate));
+                        self.status = Status::Playing;
+                        started = Some(sim);
+                    }
+                }
+                Message::Inputs {
+                    frame,
+                    advantage,
+                    ack,
+                    start,
+                    inputs,
+                    checksum,
+                } => {
+                    // packets can show up out of order, so only ever move forward
+                    if frame >= self.peer_frame {
+                        self.peer_frame = frame;
+                        self.peer_advantage = advantage;
+                    }
+                    self.peer_ack = self.peer_ack.max(ack);
+                    for (i, &input) in inputs.iter().enumerate() {
+                        match start.checked_add(i as u32) {
+                            Some(frame) => self.receive_input(frame, input),
+                            None => break,
+                        }
+                    }
+                    // the same checksum comes along in every packet until the next one is taken
+                    if let Some((frame, checksum)) =
+                        checksum.filter(|&(f, _)| f >= self.checked_up_to)
+                    {
+                        self.remote_checksums.insert(frame, checksum);
+                        self.compare_checksums();
+                    }
+                }
+                Message::Bye => {
+                    self.status = Status::Disconnected("The other player left".to_string());
+                }
+            }
+        }
+
+        let hello_due = self
+            .last_hello
+            .map_or(true, |sent| sent.elapsed() >= HELLO_INTERVAL);
+        match self.status {
+            Status::Connecting(_) if hello_due => {
+                self.send(&Message::Hello);
+                self.last_hello = Some(Instant::now());
+            }
+            Status::Connecting(_) if self.last_heard.elapsed() > TIMEOUT => {
+                self.status = Status::Disconnected("The host didn't answer".to_string());
+            }
+            Status::Playing if self.last_heard.elapsed() > TIMEOUT => {
+                self.status = Status::Disconnected("Lost connection".to_string());
+            }
+            _ => (),
+        }
+        self.spectators.drop_silent();
+        started
+    }
+
+    /// Lets someone watch the match we're hosting
+    fn receive_spectator(&mut self, from: SocketAddr, packet: &[u8]) {
+        let (sim, message) = match (&self.start, ClientMessage::decode(packet)) {
+            (Some(sim), Some(message)) if self.local_player == Player::One => (sim, message),
+            _ => return,
+        };
+        let welcome = ServerMessage::Watching {
+            field_w: sim.field_w,
+            field_h: sim.field_h,
+            rules: sim.rules,
+            tick_rate: self.tick_rate,
+        };
+        self.spectators
+            .receive(&self.socket, from, &message, &welcome);
+    }
+
+    fn receive_input(&mut self, frame: u32, input: f32) {
+        // the inputs are kept by tick, so a tick far in the future would take a lot of room
+        if frame > self.frame.saturating_add(INPUT_WINDOW)
+            || frame.saturating_add(INPUT_WINDOW) < self.frame
+        {
+            return;
+        }
+        // don't trust the other side to stick to full speed
+        let mut input = if input.is_finite() { input } else { 0.0 };
+        clamp(&mut input, -1.0, 1.0);
+        // a late copy of an input we've already forgotten
+        let index = match self.index(frame) {
+            Some(index) => index,
+            None => return,
+        };
+        if self.remote_inputs.len() <= index {
+            self.remote_inputs.resize(index + 1, None);
+        }
+        if self.remote_inputs[index].is_some() {
+            return;
+        }
+        self.remote_inputs[index] = Some(input);
+        // we already played this tick with a guess, and guessed wrong
+        if frame < self.frame && self.used_remote[index] != input {
+            self.rollback_from = Some(self.rollback_from.map_or(frame, |from| from.min(frame)));
+        }
+        while self
+            .index(self.confirmed)
+            .and_then(|index| self.remote_inputs.get(index))
+            .is_some_and(Option::is_some)
+        {
+            self.confirmed += 1;
+        }
+    }
+
+    /// Where `frame`'s inputs are in the input lists, `None` if they've been forgotten
+    fn index(&self, frame: u32) -> Option<usize> {
+        frame
+            .checked_sub(self.first_frame)
+            .map(|index| index as usize)
+    }
+
+    /// The other side's input for a tick, or a guess that it's still holding
+    /// whatever it held last time we heard
+    fn remote_input(&self, frame: u32) -> f32 {
+        let known = self
+            .index(frame)
+            .and_then(|index| self.remote_inputs.get(index).copied().flatten());
+        if let Some(input) = known {
+            return input;
+        }
+        match self
+            .confirmed
+            .checked_sub(1)
+            .and_then(|last| self.index(last))
+        {
+            Some(last) => self.remote_inputs[last].unwrap_or(0.0),
+            None => 0.0,
+        }
+    }
+
+    /// Puts the ticks both sides now agree on in the recording, and forgets the
+    /// inputs that can't be rolled back, resent or guessed from any more
+    fn forget_old_inputs(&mut self) {
+        let agreed = self.confirmed.min(self.frame);
+        if let Some(recording) = &mut self.recording {
+            let recorded = self.first_frame as usize + recording.inputs.len();
+            for frame in recorded..agreed as usize {
+                let index = frame - self.first_frame as usize;
+                let remote = self.remote_inputs[index].unwrap_or(0.0);
+                let input = match self.local_player {
+                    Player::One => Input {
+                        player_1: self.local_inputs[index],
+                        player_2: remote,
+                    },
+                    Player::Two => Input {
+                        player_1: remote,
+                        player_2: self.local_inputs[index],
+                    },
+                };
+                recording.record(input);
+            }
+        }
+
+        // the last confirmed input is what we guess with, and the other side
+        // may still be waiting for everything after what it acknowledged
+        let keep_from = agreed
+            .saturating_sub(1)
+            .min(self.peer_ack)
+            .max(self.first_frame);
+        let forget = (keep_from - self.first_frame) as usize;
+        self.local_inputs.drain(..forget);
+        self.remote_inputs.drain(..forget);
+        self.used_remote.drain(..forget);
+        self.first_frame = keep_from;
+    }
+
+    fn saved_state(&self, frame: u32) -> Option<&Simulation> {
+        self.saved
+            .iter()
+            .find(|(saved, _)| *saved == frame)
+            .map(|(_, sim)| sim)
+    }
+
+    /// Runs a single tick, remembering the state before it in case it has to be played again
+    fn step_frame(&mut self, sim: &mut Simulation, frame: u32, dt: f32) -> Vec<Event> {
+        while self.saved.back().is_some_and(|(saved, _)| *saved >= frame) {
+            self.saved.pop_back();
+        }
+        self.saved.push_back((frame, sim.clone()));
+        while self.saved.len() > MAX_PREDICTION as usize + 2 {
+            self.saved.pop_front();
+        }
+
+        let index = (frame - self.first_frame) as usize;
+        let local = self.local_inputs[index];
+        let remote = self.remote_input(frame);
+        if self.used_remote.len() <= index {
+            self.used_remote.resize(index + 1, 0.0);
+        }
+        self.used_remote[index] = remote;
+        sim.step(self.input(local, remote), dt)
+    }
+
+    /// Puts our input and the other side's on the right rackets
+    fn input(&self, local: f32, remote: f32) -> Input {
+        match self.local_player {
+            Player::One => Input {
+                player_1: local,
+                player_2: remote,
+            },
+            Player::Two => Input {
+                player_1: remote,
+                player_2: local,
+            },
+        }
+    }
+
+    /// The match so far as a replay, up to the last tick both sides agree on
+    pub fn replay(&mut self) -> Option<Replay> {
+        self.forget_old_inputs();
+        self.recording.clone()
+    }
+
+    /// Moves the match on by a tick with `local` as our input, fixing up any wrong
+    /// guesses first. Gives `None` when we're too far ahead of the other side and
+    /// have to wait for it.
+    pub fn advance(&mut self, sim: &mut Simulation, local: f32, dt: f32) -> Option<Vec<Event>> {
+        if self.status != Status::Playing {
+            return None;
+        }
+
+        if let Some(from) = self.rollback_from.take() {
+            if let Some(state) = self.saved_state(from) {
+                *sim = state.clone();
+                // the hits of the first time round have already been shown,
+                // and goals only get shown from ticks that can't change any more
+                for frame in from..self.frame {
+                    self.step_frame(sim, frame, dt);
+                }
+            }
+        }
+        self.take_checksums(sim);
+        self.send_to_spectators(sim);
+        self.forget_old_inputs();
+
+        let advantage = self.frame as i32 - self.peer_frame as i32;
+        // if we're further ahead than they are, sit out a tick now and then so they can catch up
+        let ahead = (advantage - self.peer_advantage) / 2 >= 1;
+        let stall = self.frame >= self.confirmed + MAX_PREDICTION
+            || (ahead && self.frame % SYNC_INTERVAL == 0 && self.frame > 0);
+
+        if !stall {
+            self.local_inputs.push(local);
+        }
+        self.send_inputs(advantage);
+        if stall {
+            return None;
+        }
+
+        let mut events = self.step_frame(sim, self.frame, dt);
+        self.frame += 1;
+        events.retain(|event| {
+            !matches!(
+                event,
+                Event::Goal(_) | Event::GameWon(_) | Event::MatchWon(_)
+            )
+        });
+        events.extend(self.confirmed_events(sim));
+        Some(events)
+    }
+
+    /// The goals and wins in the ticks that became certain since the last time,
+    /// worked out from the scores so a wrong guess never ends the match
+    fn confirmed_events(&mut self, sim: &Simulation) -> Vec<Event> {
+        let frame = self.confirmed.min(self.frame);
+        let state = if frame == self.frame {
+            Some(sim)
+        } else {
+            self.saved_state(frame)
+        };
+        let (scores, games, winner) = match state {
+            Some(state) => (state.scores, state.games, state.winner),
+            None => return Vec::new(),
+        };
+
+        let mut events = Vec::new();
+        let (reported_scores, reported_games, reported_winner) = self.reported;
+        for &player in &[Player::One, Player::Two] {
+            let i = player.index();
+            if games[i] > reported_games[i] {
+                events.push(Event::Goal(player));
+                events.push(Event::GameWon(player));
+            } else if scores[i] > reported_scores[i] {
+                events.push(Event::Goal(player));
+            }
+        }
+        if let (None, Some(winner)) = (reported_winner, winner) {
+            events.push(Event::MatchWon(winner));
+        }
+        self.reported = (scores, games, winner);
+        events
+    }
+
+    fn send_inputs(&mut self, advantage: i32) {
+        let start = self.peer_ack.max(self.first_frame);
+        let from = (start - self.first_frame) as usize;
+        let to = self.local_inputs.len().min(from + MAX_INPUTS_PER_PACKET);
+        let inputs = self.local_inputs.get(from..to).unwrap_or(&[]).to_vec();
+        self.send(&Message::Inputs {
+            frame: self.frame,
+            advantage,
+            ack: self.confirmed,
+            start,
+            inputs,
+            checksum: self.sent_checksum,
+        });
+    }
+
+    /// Takes checksums of the ticks that can't be rolled back any more
+    fn take_checksums(&mut self, sim: &Simulation) {
+        while self.next_checksum <= self.confirmed.min(self.frame) {
+            let frame = self.next_checksum;
+            let checksum = if frame == self.frame {
+                Some(sim.checksum())
+            } else {
+                self.saved_state(frame).map(Simulation::checksum)
+            };
+            if let Some(checksum) = checksum {
+                self.local_checksums.insert(frame, checksum);
+                self.sent_checksum = Some((frame, checksum));
+            }
+            self.next_checksum += CHECKSUM_INTERVAL;
+        }
+        self.compare_checksums();
+    }
+
+    /// Sends spectators the newest tick that can't be rolled back any more,
+    /// so they never see a guess that turns out wrong
+    fn send_to_spectators(&mut self, sim: &Simulation) {
+        let frame = self.confirmed.min(self.frame);
+        if frame < self.next_spectated {
+            return;
+        }
+        let state = if frame == self.frame {
+            Some(sim)
+        } else {
+            self.saved_state(frame)
+        };
+        if let Some(state) = state {
+            let snapshot = Snapshot::new(frame, state, false, self.spectators.count());
+            self.spectators
+                .send(&self.socket, &ServerMessage::Snapshot(snapshot));
+        }
+        self.next_spectated = frame + SPECTATOR_INTERVAL;
+    }
+
+    fn compare_checksums(&mut self) {
+        let mut compared = Vec::new();
+        for (frame, local) in &self.local_checksums {
+            if let Some(remote) = self.remote_checksums.get(frame) {
+                if local != remote && self.desync.is_none() {
+                    self.desync = Some(*frame);
+                }
+                compared.push(*frame);
+            }
+        }
+        for frame in compared {
+            self.checked_up_to = self.checked_up_to.max(frame + 1);
+            self.local_checksums.remove(&frame);
+            self.remote_checksums.remove(&frame);
+        }
+    }
+}
+
+impl Drop for NetSession {
+    fn drop(&mut self) {
+        self.send(&Message::Bye);
+    }
+}
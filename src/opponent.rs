@@ -0,0 +1,281 @@
+//! Things that can control a racket, from a human on the keyboard to
+//! a computer player that knows exactly where the ball is going.
+
+use crate::simulation::{
+    clamp, clamp_racket_y, Player, Simulation, BALL_SIZE_HALF, PLAYER_SPEED, RACKET_WIDTH_HALF,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Gives the input that moves a racket at `current_y` towards `target_y`
+/// without overshooting it, going at most `max_speed` times `PLAYER_SPEED`
+pub fn chase(current_y: f32, target_y: f32, max_speed: f32, dt: f32) -> f32 {
+    let mut axis = (current_y - target_y) / (PLAYER_SPEED * dt);
+    clamp(&mut axis, -max_speed, max_speed);
+    axis
+}
+
+/// Gives the input that moves `player`'s racket towards a pointer at `pointer_y`.
+/// The racket goes at most `max_speed` times `PLAYER_SPEED`, so it can't teleport across the field.
+pub fn follow_pointer(
+    sim: &Simulation,
+    player: Player,
+    pointer_y: f32,
+    max_speed: f32,
+    dt: f32,
+) -> f32 {
+    // aiming past the edge would just push the racket into the wall
+    let mut target_y = pointer_y;
+    clamp_racket_y(&mut target_y, sim.field_h);
+    chase(sim.racket_pos(player).y, target_y, max_speed, dt)
+}
+
+/// How good a computer player is
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Easy,
+    Medium,
+    Hard,
+    Impossible,
+}
+
+/// The numbers behind a `Level`
+#[derive(Clone, Copy, Debug)]
+pub struct Difficulty {
+    /// Seconds between each time the computer looks at the ball
+    pub reaction_delay: f32,
+    /// How far off (in pixels) the computer might aim
+    pub aim_error: f32,
+    /// Top racket speed as a fraction of `PLAYER_SPEED`
+    pub max_speed: f32,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::Easy, Level::Medium, Level::Hard, Level::Impossible];
+
+    pub fn difficulty(self) -> Difficulty {
+        match self {
+            Level::Easy => Difficulty {
+                reaction_delay: 0.35,
+                aim_error: 40.0,
+                max_speed: 0.4,
+            },
+            Level::Medium => Difficulty {
+                reaction_delay: 0.2,
+                aim_error: 20.0,
+                max_speed: 0.6,
+            },
+            Level::Hard => Difficulty {
+                reaction_delay: 0.1,
+                aim_error: 8.0,
+                max_speed: 0.85,
+            },
+            Level::Impossible => Difficulty {
+                reaction_delay: 0.0,
+                aim_error: 0.0,
+                max_speed: 1.0,
+            },
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Easy => "easy",
+            Level::Medium => "medium",
+            Level::Hard => "hard",
+            Level::Impossible => "impossible",
+        }
+    }
+}
+
+/// Anything that can move a racket
+pub trait Opponent {
+    /// Decides the input for `player`'s racket this tick.
+    /// `held` is what the keys for that racket are saying, computer players just ignore it.
+    fn think(&mut self, sim: &Simulation, player: Player, held: f32, dt: f32) -> f32;
+}
+
+/// A person on the keyboard, a gamepad or the mouse
+pub struct Human;
+
+impl Opponent for Human {
+    fn think(&mut self, _sim: &Simulation, _player: Player, held: f32, _dt: f32) -> f32 {
+        held
+    }
+}
+
+/// Keeps track of when a computer player gets to look at the ball again,
+/// and how badly it will miss this time
+struct Reaction {
+    difficulty: Difficulty,
+    timer: f32,
+    error: f32,
+    /// Seeded, so a computer player misses the same way every time it's given the same seed
+    rng: StdRng,
+}
+
+impl Reaction {
+    fn new(level: Level, seed: u64) -> Reaction {
+        Reaction {
+            difficulty: level.difficulty(),
+            timer: 0.0,
+            error: 0.0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Counts down the reaction delay, returns true when it's time to look again
+    fn ready(&mut self, dt: f32) -> bool {
+        self.timer -= dt;
+        if self.timer > 0.0 {
+            return false;
+        }
+        self.timer = self.difficulty.reaction_delay;
+        let aim_error = self.difficulty.aim_error;
+        self.error = if aim_error > 0.0 {
+            self.rng.gen_range(-aim_error, aim_error)
+        } else {
+            0.0
+        };
+        true
+    }
+}
+
+/// The original "AI", it just follows the ball up and down
+pub struct Tracker {
+    reaction: Reaction,
+    target_y: f32,
+}
+
+impl Tracker {
+    pub fn new(level: Level, seed: u64) -> Tracker {
+        Tracker {
+            reaction: Reaction::new(level, seed),
+            target_y: 0.0,
+        }
+    }
+}
+
+impl Opponent for Tracker {
+    fn think(&mut self, sim: &Simulation, player: Player, _held: f32, dt: f32) -> f32 {
+        if self.reaction.ready(dt) {
+            self.target_y = sim.ball_pos.y + self.reaction.error;
+        }
+        let racket_y = sim.racket_pos(player).y;
+        chase(
+            racket_y,
+            self.target_y,
+            self.reaction.difficulty.max_speed,
+            dt,
+        )
+    }
+}
+
+/// Works out where the ball will be when it reaches the racket, walls and all
+pub struct Predictor {
+    reaction: Reaction,
+    target_y: f32,
+}
+
+impl Predictor {
+    pub fn new(level: Level, seed: u64) -> Predictor {
+        Predictor {
+            reaction: Reaction::new(level, seed),
+            target_y: 0.0,
+        }
+    }
+}
+
+/// Where the ball will cross `x`, bouncing off the top and bottom on the way.
+/// Returns `None` if the ball is going the other way.
+pub fn predict_ball_y(sim: &Simulation, x: f32) -> Option<f32> {
+    let time = (x - sim.ball_pos.x) / sim.ball_vel.x;
+    if !time.is_finite() || time < 0.0 {
+        return None;
+    }
+
+    // unfold the bounces: the ball travels in a straight line through
+    // mirrored copies of the field, so fold that line back into the real one
+    let low = BALL_SIZE_HALF;
+    let span = sim.field_h - BALL_SIZE_HALF * 2.0;
+    if span <= 0.0 {
+        return Some(sim.field_h * 0.5);
+    }
+    let unfolded = sim.ball_pos.y + sim.ball_vel.y * time - low;
+    let folded = unfolded.rem_euclid(span * 2.0);
+    let y = if folded > span {
+        span * 2.0 - folded
+    } else {
+        folded
+    };
+    Some(low + y)
+}
+
+impl Opponent for Predictor {
+    fn think(&mut self, sim: &Simulation, player: Player, _held: f32, dt: f32) -> f32 {
+        let racket_pos = sim.racket_pos(player);
+        if self.reaction.ready(dt) {
+            let face_x = match player {
+                Player::One => racket_pos.x + RACKET_WIDTH_HALF + BALL_SIZE_HALF,
+                Player::Two => racket_pos.x - RACKET_WIDTH_HALF - BALL_SIZE_HALF,
+            };
+            // head back to the middle while the ball is going away
+            self.target_y = match predict_ball_y(sim, face_x) {
+                Some(y) => y + self.reaction.error,
+                None => sim.field_h * 0.5,
+            };
+        }
+        chase(
+            racket_pos.y,
+            self.target_y,
+            self.reaction.difficulty.max_speed,
+            dt,
+        )
+    }
+}
+
+/// The things you can pick to control a side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControllerKind {
+    Human,
+    /// A human whose racket follows the mouse
+    Mouse,
+    Tracker(Level),
+    Predictor(Level),
+}
+
+impl ControllerKind {
+    /// Makes the controller, `seed` decides how computer players miss
+    pub fn build(self, seed: u64) -> Box<dyn Opponent> {
+        match self {
+            ControllerKind::Human | ControllerKind::Mouse => Box::new(Human),
+            ControllerKind::Tracker(level) => Box::new(Tracker::new(level, seed)),
+            ControllerKind::Predictor(level) => Box::new(Predictor::new(level, seed)),
+        }
+    }
+
+    /// Every kind there is, in the order menus cycle through them
+    pub fn all() -> Vec<ControllerKind> {
+        let mut kinds = vec![ControllerKind::Human, ControllerKind::Mouse];
+        kinds.extend(
+            Level::ALL
+                .iter()
+                .map(|&level| ControllerKind::Tracker(level)),
+        );
+        kinds.extend(
+            Level::ALL
+                .iter()
+                .map(|&level| ControllerKind::Predictor(level)),
+        );
+        kinds
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            ControllerKind::Human => "human".to_string(),
+            ControllerKind::Mouse => "mouse".to_string(),
+            ControllerKind::Tracker(level) => format!("tracker ({})", level.name()),
+            ControllerKind::Predictor(level) => format!("predictive ({})", level.name()),
+        }
+    }
+}
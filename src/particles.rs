@@ -0,0 +1,195 @@
+//! Sparks, dust and bursts flying off the ball.
+//! Particles live in a pool that's allocated once: the live ones are kept at
+//! the front, oldest first, and dead ones get swapped behind them, so particles
+//! coming and going never allocate anything. They're all drawn in one go with a `SpriteBatch`.
+
+use ggez::graphics::{self, spritebatch::SpriteBatch, DrawParam};
+use ggez::nalgebra as na;
+use ggez::{Context, GameResult};
+use rand::Rng;
+use std::f32::consts::PI;
+
+/// How a bunch of particles flies off
+pub struct Emitter {
+    pub count: usize,
+    /// Pixels per second, the slowest and the fastest
+    pub speed: (f32, f32),
+    /// How far from the direction they're sent in they can go, in radians either way
+    pub spread: f32,
+    /// Pixels across
+    pub size: f32,
+    /// Seconds they last, the shortest and the longest
+    pub lifetime: (f32, f32),
+    /// How much of their speed is left after a second
+    pub drag: f32,
+    /// Whether they light up what's around them when lighting is on
+    pub lit: bool,
+}
+
+/// Off the ball when a racket hits it
+pub const SPARKS: Emitter = Emitter {
+    count: 24,
+    speed: (150.0, 450.0),
+    spread: 0.8,
+    size: 3.0,
+    lifetime: (0.2, 0.5),
+    drag: 0.05,
+    lit: true,
+};
+
+/// Off the wall when the ball bounces
+pub const DUST: Emitter = Emitter {
+    count: 12,
+    speed: (30.0, 120.0),
+    spread: 1.2,
+    size: 4.0,
+    lifetime: (0.3, 0.8),
+    drag: 0.1,
+    lit: false,
+};
+
+/// Where the ball went out when someone scores
+pub const BURST: Emitter = Emitter {
+    count: 80,
+    speed: (100.0, 500.0),
+    spread: PI,
+    size: 4.0,
+    lifetime: (0.4, 1.0),
+    drag: 0.2,
+    lit: true,
+};
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    pos: na::Point2<f32>,
+    vel: na::Vector2<f32>,
+    color: [f32; 4],
+    size: f32,
+    drag: f32,
+    lit: bool,
+    age: f32,
+    lifetime: f32,
+}
+
+impl Particle {
+    /// 1 when it's new down to 0 when it's gone
+    fn life_left(&self) -> f32 {
+        (1.0 - self.age / self.lifetime).max(0.0)
+    }
+}
+
+/// Every particle on the field
+pub struct Particles {
+    /// Live particles first from the oldest to the newest, then the dead ones waiting to be used again
+    pool: Vec<Particle>,
+    live: usize,
+    batch: SpriteBatch,
+}
+
+impl Particles {
+    /// Makes room for `max` particles at once, more than that don't get emitted
+    pub fn new(ctx: &mut Context, max: usize) -> GameResult<Particles> {
+        let dead = Particle {
+            pos: na::Point2::origin(),
+            vel: na::Vector2::zeros(),
+            color: [0.0; 4],
+            size: 0.0,
+            drag: 1.0,
+            lit: false,
+            age: 0.0,
+            lifetime: 1.0,
+        };
+        let image = graphics::Image::solid(ctx, 1, graphics::WHITE)?;
+        Ok(Particles {
+            pool: vec![dead; max],
+            live: 0,
+            batch: SpriteBatch::new(image),
+        })
+    }
+
+    /// Sends `emitter`'s particles off from `pos` around the angle `direction`, in `color`
+    pub fn emit(
+        &mut self,
+        emitter: &Emitter,
+        pos: na::Point2<f32>,
+        direction: f32,
+        color: [f32; 4],
+    ) {
+        // particles are only for show and left out of replays and netplay,
+        // so they don't need the simulation's seeded randomness
+        let mut rng = rand::thread_rng();
+        for _ in 0..emitter.count {
+            if self.live == self.pool.len() {
+                return;
+            }
+            let angle = direction + rng.gen_range(-emitter.spread, emitter.spread);
+            let speed = rng.gen_range(emitter.speed.0, emitter.speed.1);
+            self.pool[self.live] = Particle {
+                pos,
+                vel: na::Vector2::new(angle.cos(), angle.sin()) * speed,
+                color,
+                size: emitter.size,
+                drag: emitter.drag,
+                lit: emitter.lit,
+                age: 0.0,
+                lifetime: rng.gen_range(emitter.lifetime.0, emitter.lifetime.1),
+            };
+            self.live += 1;
+        }
+    }
+
+    /// Moves everything on by `dt` seconds and puts the particles that ran out back in the pool
+    pub fn update(&mut self, dt: f32) {
+        // the ones still going get moved up over the dead ones, staying in order
+        let mut kept = 0;
+        for i in 0..self.live {
+            let particle = &mut self.pool[i];
+            particle.age += dt;
+            if particle.age >= particle.lifetime {
+                continue;
+            }
+            particle.pos += particle.vel * dt;
+            particle.vel *= particle.drag.powf(dt);
+            self.pool.swap(kept, i);
+            kept += 1;
+        }
+        self.live = kept;
+    }
+
+    /// Gets rid of every particle, e.g. when a replay skips ahead
+    pub fn clear(&mut self) {
+        self.live = 0;
+    }
+
+    /// Where the lit particles are, their color and how bright they still are
+    /// from 1 down to 0, the newest first
+    pub fn lights(&self) -> impl Iterator<Item = (na::Point2<f32>, [f32; 4], f32)> + '_ {
+        self.pool[..self.live]
+            .iter()
+            .rev()
+            .filter(|particle| particle.lit)
+            .map(|particle| (particle.pos, particle.color, particle.life_left()))
+    }
+
+    /// Draws every live particle, fading out as it gets older
+    pub fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        if self.live == 0 {
+            return Ok(());
+        }
+        self.batch.clear();
+        for particle in &self.pool[..self.live] {
+            let [r, g, b, a] = particle.color;
+            let half = particle.size * 0.5;
+            self.batch.add(
+                DrawParam::new()
+                    .dest(na::Point2::new(
+                        particle.pos.x - half,
+                        particle.pos.y - half,
+                    ))
+                    .scale(na::Vector2::new(particle.size, particle.size))
+                    .color(graphics::Color::new(r, g, b, a * particle.life_left())),
+            );
+        }
+        graphics::draw(ctx, &self.batch, DrawParam::default())
+    }
+}
@@ -0,0 +1,116 @@
+//! Watching a recorded match with `--replay`. It can be paused, sped up,
+//! slowed down and skipped around in.
+
+use pong::replay::Replay;
+use pong::simulation::{Event, Simulation};
+
+/// How far apart (in ticks) we keep copies of the match, so seeking only has
+/// to play forward from the closest one instead of from the start
+const KEYFRAME_INTERVAL: usize = 600;
+/// The playback speeds to pick from
+pub const SPEEDS: [f32; 7] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+pub struct Playback {
+    replay: Replay,
+    /// The tick the simulation is about to run
+    tick: usize,
+    /// The match at every `KEYFRAME_INTERVAL` ticks, as far as we've got
+    keyframes: Vec<Simulation>,
+    /// One of `SPEEDS`
+    pub speed: f32,
+    pub paused: bool,
+    /// Ticks owed at the current speed, slow motion only runs one every few updates
+    owed: f32,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Playback {
+        let start = replay.start();
+        Playback {
+            replay,
+            tick: 0,
+            keyframes: vec![start],
+            speed: 1.0,
+            paused: false,
+            owed: 0.0,
+        }
+    }
+
+    pub fn tick_rate(&self) -> u32 {
+        self.replay.tick_rate
+    }
+
+    /// Seconds into the match
+    pub fn time(&self) -> f32 {
+        self.tick as f32 / self.replay.tick_rate as f32
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.replay.duration()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.tick >= self.replay.inputs.len()
+    }
+
+    /// Runs the next recorded tick
+    fn step(&mut self, sim: &mut Simulation) -> Vec<Event> {
+        let input = self.replay.inputs[self.tick];
+        let events = sim.step(input, 1.0 / self.replay.tick_rate as f32);
+        self.tick += 1;
+        if self.tick % KEYFRAME_INTERVAL == 0
+            && self.keyframes.len() == self.tick / KEYFRAME_INTERVAL
+        {
+            self.keyframes.push(sim.clone());
+        }
+        events
+    }
+
+    /// Called once a game tick, runs however many recorded ticks the speed asks for.
+    /// Gives `None` if none were run, e.g. while paused or in slow motion.
+    pub fn advance(&mut self, sim: &mut Simulation) -> Option<Vec<Event>> {
+        if self.paused || self.finished() {
+            self.owed = 0.0;
+            return None;
+        }
+        self.owed += self.speed;
+        let mut events = Vec::new();
+        let mut ran = false;
+        while self.owed >= 1.0 && !self.finished() {
+            self.owed -= 1.0;
+            events.extend(self.step(sim));
+            ran = true;
+        }
+        if ran {
+            Some(events)
+        } else {
+            None
+        }
+    }
+
+    /// Jumps `seconds` forwards or backwards, putting the match at that point in `sim`
+    pub fn seek(&mut self, sim: &mut Simulation, seconds: f32) {
+        let offset = (seconds * self.replay.tick_rate as f32) as isize;
+        let target = (self.tick as isize + offset).clamp(0, self.replay.inputs.len() as isize);
+        self.seek_to(sim, target as usize);
+    }
+
+    /// Goes back to the very start
+    pub fn rewind(&mut self, sim: &mut Simulation) {
+        self.seek_to(sim, 0);
+    }
+
+    fn seek_to(&mut self, sim: &mut Simulation, target: usize) {
+        // going forward from where we are beats starting over from an older keyframe
+        let keyframe = (target / KEYFRAME_INTERVAL).min(self.keyframes.len() - 1);
+        if target < self.tick || keyframe * KEYFRAME_INTERVAL > self.tick {
+            *sim = self.keyframes[keyframe].clone();
+            self.tick = keyframe * KEYFRAME_INTERVAL;
+        }
+        while self.tick < target {
+            // what happened along the way isn't shown, only where it ended up
+            self.step(sim);
+        }
+        self.owed = 0.0;
+    }
+}
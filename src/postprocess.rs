@@ -0,0 +1,380 @@
+//! Effects laid over the whole picture once the game is drawn.
+//! The game gets drawn to a canvas instead of the window, bloom adds a blurred
+//! copy of the light map onto it, and then every effect that's switched on draws
+//! it into the other canvas through its shader, the last one onto the window.
+//! The menus and text go on top afterwards so they stay readable.
+
+use crate::lighting::{cover_window, window_canvas};
+use crate::settings::PostProcessSettings;
+use crate::shaders;
+use gfx::{self, *};
+use ggez::graphics::{self, BlendMode, Canvas, Shader};
+use ggez::{Context, GameError, GameResult};
+
+gfx_defines! {
+    /// What the effects get told, not all of them use everything
+    constant Effect {
+        /// Which way the blur goes
+        direction: [f32; 2] = "u_Direction",
+        screen_size: [f32; 2] = "u_ScreenSize",
+        strength: f32 = "u_Strength",
+        amount: f32 = "u_Amount",
+    }
+
+    /// For `resources/dimmer_150.glslf`
+    constant Dim {
+        rate: f32 = "u_Rate",
+    }
+}
+
+/// Half a gaussian blur, run across and then down the light map for bloom
+const BLUR_SHADER_FILE: &str = "/blur_150.glslf";
+const BLUR_SHADER_SOURCE: &str = include_str!("../resources/blur_150.glslf");
+
+/// Moves the red and blue of every pixel apart, more towards the edges
+const CHROMATIC_ABERRATION_SHADER_FILE: &str = "/chromatic_aberration_150.glslf";
+const CHROMATIC_ABERRATION_SHADER_SOURCE: &str =
+    include_str!("../resources/chromatic_aberration_150.glslf");
+
+/// Bends the picture and draws scanlines over it
+const CRT_SHADER_FILE: &str = "/crt_150.glslf";
+const CRT_SHADER_SOURCE: &str = include_str!("../resources/crt_150.glslf");
+
+/// Darkens the corners
+const VIGNETTE_SHADER_FILE: &str = "/vignette_150.glslf";
+const VIGNETTE_SHADER_SOURCE: &str = include_str!("../resources/vignette_150.glslf");
+
+/// Multiplies everything by a number, for the brightness
+const DIMMER_SHADER_FILE: &str = "/dimmer_150.glslf";
+const DIMMER_SHADER_SOURCE: &str = include_str!("../resources/dimmer_150.glslf");
+
+const VERTEX_SHADER_SOURCE: &[u8] = include_bytes!("../resources/basic_150.glslv");
+
+/// The fragment shader files in the resources directory, to watch for changes
+pub const SHADER_FILES: [&str; 5] = [
+    BLUR_SHADER_FILE,
+    CHROMATIC_ABERRATION_SHADER_FILE,
+    CRT_SHADER_FILE,
+    VIGNETTE_SHADER_FILE,
+    DIMMER_SHADER_FILE,
+];
+/// The copies of them built into the game
+const BUILT_IN_SHADERS: [&str; 5] = [
+    BLUR_SHADER_SOURCE,
+    CHROMATIC_ABERRATION_SHADER_SOURCE,
+    CRT_SHADER_SOURCE,
+    VIGNETTE_SHADER_SOURCE,
+    DIMMER_SHADER_SOURCE,
+];
+
+/// The glow gets blurred at this fraction of the window size, it's blurry anyway
+const GLOW_RESOLUTION: f32 = 0.5;
+
+/// The effects that take the whole picture and draw it again, in the order they run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    ChromaticAberration,
+    Crt,
+    Vignette,
+    Brightness,
+}
+
+/// The post-processing shaders
+struct Shaders {
+    blur: Shader<Effect>,
+    chromatic_aberration: Shader<Effect>,
+    crt: Shader<Effect>,
+    vignette: Shader<Effect>,
+    dimmer: Shader<Dim>,
+}
+
+impl Shaders {
+    /// Compiles the shader `sources`, in the order of `SHADER_FILES`
+    fn compile<S: AsRef<str>>(ctx: &mut Context, sources: &[S; 5]) -> GameResult<Shaders> {
+        // the real uniforms get sent before every draw
+        let effect = Effect {
+            direction: [0.0, 0.0],
+            screen_size: [1.0, 1.0],
+            strength: 0.0,
+            amount: 0.0,
+        };
+        let mut compile = |source: &S| {
+            Shader::from_u8(
+                ctx,
+                VERTEX_SHADER_SOURCE,
+                source.as_ref().as_bytes(),
+                effect,
+                "Effect",
+                None,
+            )
+        };
+        let blur = compile(&sources[0])?;
+        let chromatic_aberration = compile(&sources[1])?;
+        let crt = compile(&sources[2])?;
+        let vignette = compile(&sources[3])?;
+        let dimmer = Shader::from_u8(
+            ctx,
+            VERTEX_SHADER_SOURCE,
+            sources[4].as_ref().as_bytes(),
+            Dim { rate: 1.0 },
+            "Dim",
+            // the alpha gets dimmed too, which mustn't make it see-through
+            Some(&[BlendMode::Replace]),
+        )?;
+        Ok(Shaders {
+            blur,
+            chromatic_aberration,
+            crt,
+            vignette,
+            dimmer,
+        })
+    }
+}
+
+/// The canvases and shaders of the post-processing chain
+pub struct PostProcess {
+    settings: PostProcessSettings,
+    /// The size of the window
+    size: (f32, f32),
+    /// The game gets drawn into the first one, and each effect draws from one into the other
+    canvases: [Canvas; 2],
+    /// The light map gets blurred across into the first and then down into the second
+    glow: [Canvas; 2],
+    shaders: Shaders,
+}
+
+impl PostProcess {
+    /// Sets up the effects in `settings` for a `window_w` by `window_h` window.
+    /// Fails if the graphics card can't compile the shaders.
+    pub fn new(
+        ctx: &mut Context,
+        settings: &PostProcessSettings,
+        window_w: f32,
+        window_h: f32,
+    ) -> GameResult<PostProcess> {
+        let sources = [
+            shaders::source(ctx, BLUR_SHADER_FILE, BLUR_SHADER_SOURCE),
+            shaders::source(
+                ctx,
+                CHROMATIC_ABERRATION_SHADER_FILE,
+                CHROMATIC_ABERRATION_SHADER_SOURCE,
+            ),
+            shaders::source(ctx, CRT_SHADER_FILE, CRT_SHADER_SOURCE),
+            shaders::source(ctx, VIGNETTE_SHADER_FILE, VIGNETTE_SHADER_SOURCE),
+            shaders::source(ctx, DIMMER_SHADER_FILE, DIMMER_SHADER_SOURCE),
+        ];
+        let shaders = match Shaders::compile(ctx, &sources) {
+            Ok(shaders) => shaders,
+            Err(e) if sources != BUILT_IN_SHADERS => {
+                eprintln!(
+                    "the post-processing shaders in resources don't compile, using the built in ones: {}",
+                    e
+                );
+                Shaders::compile(ctx, &BUILT_IN_SHADERS)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        let (canvases, glow) = PostProcess::canvases(ctx, window_w, window_h)?;
+        Ok(PostProcess {
+            settings: settings.clamped(),
+            size: (window_w, window_h),
+            canvases,
+            glow,
+            shaders,
+        })
+    }
+
+    fn canvases(
+        ctx: &mut Context,
+        window_w: f32,
+        window_h: f32,
+    ) -> GameResult<([Canvas; 2], [Canvas; 2])> {
+        let (glow_w, glow_h) = (window_w * GLOW_RESOLUTION, window_h * GLOW_RESOLUTION);
+        let canvases = [
+            window_canvas(ctx, window_w, window_h, None)?,
+            window_canvas(ctx, window_w, window_h, None)?,
+        ];
+        let glow = [
+            window_canvas(ctx, glow_w, glow_h, None)?,
+            // The glow is added on top of the light that's already there
+            window_canvas(ctx, glow_w, glow_h, Some(BlendMode::Add))?,
+        ];
+        Ok((canvases, glow))
+    }
+
+    /// Remakes the canvases for a window that changed size
+    pub fn resize(&mut self, ctx: &mut Context, window_w: f32, window_h: f32) -> GameResult {
+        let (canvases, glow) = PostProcess::canvases(ctx, window_w, window_h)?;
+        self.size = (window_w, window_h);
+        self.canvases = canvases;
+        self.glow = glow;
+        Ok(())
+    }
+
+    /// Compiles the shader files in resources again and switches to them.
+    /// If any of them doesn't compile the old ones are kept and the error returned.
+    pub fn reload_shaders(&mut self, ctx: &mut Context) -> GameResult {
+        let mut read = |file: &str| {
+            shaders::read(ctx, file)
+                .map_err(|e| GameError::ResourceLoadError(format!("{}: {}", file, e)))
+        };
+        let sources = [
+            read(BLUR_SHADER_FILE)?,
+            read(CHROMATIC_ABERRATION_SHADER_FILE)?,
+            read(CRT_SHADER_FILE)?,
+            read(VIGNETTE_SHADER_FILE)?,
+            read(DIMMER_SHADER_FILE)?,
+        ];
+        self.shaders = Shaders::compile(ctx, &sources)?;
+        Ok(())
+    }
+
+    /// Starts a frame. Everything drawn from here until `finish` gets the effects.
+    pub fn begin(&self, ctx: &mut Context) {
+        graphics::set_canvas(ctx, Some(&self.canvases[0]));
+    }
+
+    /// Adds the glow of `light_map` if there is one, runs the effects and
+    /// draws the result onto the window
+    pub fn finish(&self, ctx: &mut Context, light_map: Option<(&Canvas, f32, f32)>) -> GameResult {
+        if let (true, Some(light_map)) = (self.settings.bloom, light_map) {
+            self.bloom(ctx, light_map)?;
+        }
+
+        let (width, height) = self.size;
+        let stages = self.stages();
+        let mut from = 0;
+        for (i, &stage) in stages.iter().enumerate() {
+            if i + 1 == stages.len() {
+                graphics::set_canvas(ctx, None);
+            } else {
+                graphics::set_canvas(ctx, Some(&self.canvases[1 - from]));
+            }
+            graphics::clear(ctx, graphics::BLACK);
+            self.draw_stage(ctx, stage, &self.canvases[from])?;
+            from = 1 - from;
+        }
+        // bloom on its own still has to get onto the window
+        if stages.is_empty() {
+            graphics::set_canvas(ctx, None);
+            graphics::clear(ctx, graphics::BLACK);
+            let param = cover_window(ctx, width, height);
+            graphics::draw(ctx, &self.canvases[0], param)?;
+        }
+        Ok(())
+    }
+
+    /// The effects that are switched on, in order
+    fn stages(&self) -> Vec<Stage> {
+        let settings = &self.settings;
+        let mut stages = Vec::new();
+        if settings.chromatic_aberration {
+            stages.push(Stage::ChromaticAberration);
+        }
+        if settings.crt {
+            stages.push(Stage::Crt);
+        }
+        if settings.vignette {
+            stages.push(Stage::Vignette);
+        }
+        if settings.brightness != 1.0 {
+            stages.push(Stage::Brightness);
+        }
+        stages
+    }
+
+    /// Blurs the light map across and then down and adds it onto the game
+    fn bloom(
+        &self,
+        ctx: &mut Context,
+        (light_map, light_w, light_h): (&Canvas, f32, f32),
+    ) -> GameResult {
+        let (width, height) = self.size;
+        let (glow_w, glow_h) = (width * GLOW_RESOLUTION, height * GLOW_RESOLUTION);
+        let mut effect = Effect {
+            direction: [1.0, 0.0],
+            screen_size: [glow_w, glow_h],
+            strength: 1.0,
+            amount: self.settings.bloom_radius,
+        };
+        {
+            let _shader_lock = graphics::use_shader(ctx, &self.shaders.blur);
+
+            graphics::set_canvas(ctx, Some(&self.glow[0]));
+            graphics::clear(ctx, graphics::BLACK);
+            self.shaders.blur.send(ctx, effect)?;
+            let param = cover_window(ctx, light_w, light_h);
+            graphics::draw(ctx, light_map, param)?;
+
+            graphics::set_canvas(ctx, Some(&self.glow[1]));
+            graphics::clear(ctx, graphics::BLACK);
+            effect.direction = [0.0, 1.0];
+            effect.strength = self.settings.bloom_strength;
+            self.shaders.blur.send(ctx, effect)?;
+            let param = cover_window(ctx, glow_w, glow_h);
+            graphics::draw(ctx, &self.glow[0], param)?;
+        }
+        graphics::set_canvas(ctx, Some(&self.canvases[0]));
+        let param = cover_window(ctx, glow_w, glow_h);
+        graphics::draw(ctx, &self.glow[1], param)
+    }
+
+    /// Draws `source` through the shader of `stage`
+    fn draw_stage(&self, ctx: &mut Context, stage: Stage, source: &Canvas) -> GameResult {
+        let settings = &self.settings;
+        let shaders = &self.shaders;
+        match stage {
+            Stage::ChromaticAberration => self.draw_effect(
+                ctx,
+                &shaders.chromatic_aberration,
+                source,
+                0.0,
+                settings.chromatic_aberration_pixels,
+            ),
+            Stage::Crt => self.draw_effect(
+                ctx,
+                &shaders.crt,
+                source,
+                settings.scanlines,
+                settings.crt_curvature,
+            ),
+            Stage::Vignette => self.draw_effect(
+                ctx,
+                &shaders.vignette,
+                source,
+                settings.vignette_strength,
+                0.0,
+            ),
+            Stage::Brightness => {
+                let _shader_lock = graphics::use_shader(ctx, &shaders.dimmer);
+                let rate = settings.brightness;
+                shaders.dimmer.send(ctx, Dim { rate })?;
+                let param = cover_window(ctx, self.size.0, self.size.1);
+                graphics::draw(ctx, source, param)
+            }
+        }
+    }
+
+    /// Draws `source` through `shader`, telling it `strength` and `amount`
+    fn draw_effect(
+        &self,
+        ctx: &mut Context,
+        shader: &Shader<Effect>,
+        source: &Canvas,
+        strength: f32,
+        amount: f32,
+    ) -> GameResult {
+        let (width, height) = self.size;
+        let _shader_lock = graphics::use_shader(ctx, shader);
+        let effect = Effect {
+            direction: [0.0, 0.0],
+            screen_size: [width, height],
+            strength,
+            amount,
+        };
+        shader.send(ctx, effect)?;
+        let param = cover_window(ctx, width, height);
+        graphics::draw(ctx, source, param)
+    }
+}
@@ -0,0 +1,279 @@
+//! What the dedicated server and the games connected to it say to each other.
+//! The server runs the only real simulation, clients just send their racket
+//! input and draw whatever the latest snapshot says. Spectators get the same
+//! snapshots, they just don't get a racket.
+
+use crate::rules::Ruleset;
+use crate::simulation::{check_tick_rate, Player, Simulation};
+use crate::wire::{Reader, Writer};
+use std::time::Duration;
+
+/// Starts every packet so stray traffic on the port gets ignored
+const MAGIC: &[u8; 4] = b"RPSV";
+/// Bumped whenever the packets change, so old clients get ignored instead of misunderstood
+const PROTOCOL_VERSION: u8 = 4;
+/// The UDP port the server listens on unless told otherwise
+pub const DEFAULT_PORT: u16 = 7100;
+/// How long either side waits to hear from the other before giving up on it
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+/// Big enough for any packet in here
+pub const MAX_PACKET_SIZE: usize = 512;
+
+/// Everything a client needs to draw a moment of the match
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Snapshot {
+    /// The server tick this was taken on, newer snapshots have bigger ticks
+    pub tick: u32,
+    pub player_1_y: f32,
+    pub player_2_y: f32,
+    pub player_1_vel: f32,
+    pub player_2_vel: f32,
+    pub ball_pos: [f32; 2],
+    pub ball_vel: [f32; 2],
+    pub scores: [u32; 2],
+    pub games: [u32; 2],
+    pub server: Player,
+    pub serve_timer: f32,
+    pub winner: Option<Player>,
+    /// Who touched the ball last, so clients can colour it without seeing every hit
+    pub last_hitter: Option<Player>,
+    /// The server is waiting for a second player before the match starts
+    pub waiting: bool,
+    /// How many people are watching the match
+    pub spectators: u8,
+}
+
+impl Snapshot {
+    pub fn new(tick: u32, sim: &Simulation, waiting: bool, spectators: u8) -> Snapshot {
+        Snapshot {
+            tick,
+            player_1_y: sim.player_1_pos.y,
+            player_2_y: sim.player_2_pos.y,
+            player_1_vel: sim.player_1_vel,
+            player_2_vel: sim.player_2_vel,
+            ball_pos: [sim.ball_pos.x, sim.ball_pos.y],
+            ball_vel: [sim.ball_vel.x, sim.ball_vel.y],
+            scores: sim.scores,
+            games: sim.games,
+            server: sim.server,
+            serve_timer: sim.serve_timer,
+            winner: sim.winner,
+            last_hitter: sim.last_hitter,
+            waiting,
+            spectators,
+        }
+    }
+
+    /// Makes `sim` look like the server's simulation did when this was taken
+    pub fn apply(&self, sim: &mut Simulation) {
+        sim.player_1_pos.y = self.player_1_y;
+        sim.player_2_pos.y = self.player_2_y;
+        sim.player_1_vel = self.player_1_vel;
+        sim.player_2_vel = self.player_2_vel;
+        sim.ball_pos.x = self.ball_pos[0];
+        sim.ball_pos.y = self.ball_pos[1];
+        sim.ball_vel.x = self.ball_vel[0];
+        sim.ball_vel.y = self.ball_vel[1];
+        sim.scores = self.scores;
+        sim.games = self.games;
+        sim.server = self.server;
+        sim.serve_timer = self.serve_timer;
+        sim.winner = self.winner;
+        sim.last_hitter = self.last_hitter;
+    }
+
+    fn write(&self, w: &mut Writer) {
+        w.u32(self.tick);
+        for &value in &[
+            self.player_1_y,
+            self.player_2_y,
+            self.player_1_vel,
+            self.player_2_vel,
+            self.ball_pos[0],
+            self.ball_pos[1],
+            self.ball_vel[0],
+            self.ball_vel[1],
+        ] {
+            w.f32(value);
+        }
+        for &value in self.scores.iter().chain(self.games.iter()) {
+            w.u32(value);
+        }
+        w.player(self.server);
+        w.f32(self.serve_timer);
+        w.maybe_player(self.winner);
+        w.maybe_player(self.last_hitter);
+        w.bool(self.waiting);
+        w.u8(self.spectators);
+    }
+
+    fn read(r: &mut Reader) -> Option<Snapshot> {
+        Some(Snapshot {
+            tick: r.u32()?,
+            player_1_y: r.f32()?,
+            player_2_y: r.f32()?,
+            player_1_vel: r.f32()?,
+            player_2_vel: r.f32()?,
+            ball_pos: [r.f32()?, r.f32()?],
+            ball_vel: [r.f32()?, r.f32()?],
+            scores: [r.u32()?, r.u32()?],
+            games: [r.u32()?, r.u32()?],
+            server: r.player()?,
+            serve_timer: r.f32()?,
+            winner: r.maybe_player()?,
+            last_hitter: r.maybe_player()?,
+            waiting: r.bool()?,
+            spectators: r.u8()?,
+        })
+    }
+}
+
+fn header() -> Writer {
+    let mut w = Writer::new();
+    w.bytes(MAGIC);
+    w.u8(PROTOCOL_VERSION);
+    w
+}
+
+fn check_header(r: &mut Reader) -> Option<()> {
+    if r.bytes(MAGIC.len())? != MAGIC || r.u8()? != PROTOCOL_VERSION {
+        return None;
+    }
+    Some(())
+}
+
+/// From a game to the server
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    /// Sent until the server answers with `Welcome` or `Full`
+    Join,
+    /// The client's racket input, sent every tick.
+    /// `sequence` goes up by one each time so late packets can be thrown away.
+    Input {
+        sequence: u32,
+        axis: f32,
+    },
+    Leave,
+    /// Sent every now and then by someone who only wants to watch, the server
+    /// answers with `Watching` and keeps sending them snapshots until they go quiet
+    Watch,
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = header();
+        match self {
+            ClientMessage::Join => w.u8(0),
+            ClientMessage::Input { sequence, axis } => {
+                w.u8(1);
+                w.u32(*sequence);
+                w.f32(*axis);
+            }
+            ClientMessage::Leave => w.u8(2),
+            ClientMessage::Watch => w.u8(3),
+        }
+        w.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<ClientMessage> {
+        let mut r = Reader::new(bytes);
+        check_header(&mut r)?;
+        match r.u8()? {
+            0 => Some(ClientMessage::Join),
+            1 => Some(ClientMessage::Input {
+                sequence: r.u32()?,
+                axis: r.f32()?,
+            }),
+            2 => Some(ClientMessage::Leave),
+            3 => Some(ClientMessage::Watch),
+            _ => None,
+        }
+    }
+}
+
+/// From the server to a game
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    /// The client got a racket, and this is the match it'll be playing
+    Welcome {
+        player: Player,
+        field_w: f32,
+        field_h: f32,
+        rules: Ruleset,
+        /// The client runs at the server's tick rate, so snapshots come as often as it expects
+        tick_rate: u32,
+    },
+    /// Both rackets are taken
+    Full,
+    Snapshot(Snapshot),
+    /// The client is watching, and this is the match it'll be seeing
+    Watching {
+        field_w: f32,
+        field_h: f32,
+        rules: Ruleset,
+        tick_rate: u32,
+    },
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = header();
+        match self {
+            ServerMessage::Welcome {
+                player,
+                field_w,
+                field_h,
+                rules,
+                tick_rate,
+            } => {
+                w.u8(0);
+                w.player(*player);
+                w.f32(*field_w);
+                w.f32(*field_h);
+                w.rules(rules);
+                w.u32(*tick_rate);
+            }
+            ServerMessage::Full => w.u8(1),
+            ServerMessage::Snapshot(snapshot) => {
+                w.u8(2);
+                snapshot.write(&mut w);
+            }
+            ServerMessage::Watching {
+                field_w,
+                field_h,
+                rules,
+                tick_rate,
+            } => {
+                w.u8(3);
+                w.f32(*field_w);
+                w.f32(*field_h);
+                w.rules(rules);
+                w.u32(*tick_rate);
+            }
+        }
+        w.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<ServerMessage> {
+        let mut r = Reader::new(bytes);
+        check_header(&mut r)?;
+        match r.u8()? {
+            0 => Some(ServerMessage::Welcome {
+                player: r.player()?,
+                field_w: r.f32()?,
+                field_h: r.f32()?,
+                rules: r.rules()?,
+                tick_rate: check_tick_rate(r.u32()?).ok()?,
+            }),
+            1 => Some(ServerMessage::Full),
+            2 => Some(ServerMessage::Snapshot(Snapshot::read(&mut r)?)),
+            3 => Some(ServerMessage::Watching {
+                field_w: r.f32()?,
+                field_h: r.f32()?,
+                rules: r.rules()?,
+                tick_rate: check_tick_rate(r.u32()?).ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
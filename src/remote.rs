@@ -0,0 +1,316 @@
+//! Playing on a `pong-server`. The server runs the match, we just send our
+//! racket input and show the snapshots it sends back.
+//!
+//! Spectators talk to the server the same way, to a `pong-server` or a game
+//! hosting over `--host`. They don't need to see things the moment they happen,
+//! so their snapshots sit in a short buffer first to even out how they arrive.
+
+use pong::protocol::{ClientMessage, ServerMessage, Snapshot, MAX_PACKET_SIZE, TIMEOUT};
+use pong::simulation::{Event, Player, Simulation, TICK_RATE};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How often we ask to join until the server answers
+const JOIN_INTERVAL: Duration = Duration::from_millis(250);
+/// How often spectators remind the server they're still watching
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// How many ticks behind the newest snapshot spectators are shown the match
+const SPECTATOR_DELAY: f32 = 12.0;
+/// How quickly a spectator's playback drifts back to `SPECTATOR_DELAY` behind,
+/// as a fraction of the difference per tick
+const SPECTATOR_CATCH_UP: f32 = 0.02;
+/// Further behind or ahead than this and playback jumps straight back
+const SPECTATOR_MAX_DRIFT: f32 = 120.0;
+
+/// Where the connection is at
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Connecting(SocketAddr),
+    /// We have a racket but the other one is still free, or we're watching
+    /// and the match hasn't started yet
+    WaitingForOpponent,
+    Playing,
+    /// It's over, and this is why
+    Disconnected(String),
+}
+
+pub struct ServerConnection {
+    socket: UdpSocket,
+    server: SocketAddr,
+    status: Status,
+    /// The racket the server gave us
+    pub local_player: Player,
+    /// We're only watching, there's no racket
+    pub spectating: bool,
+    last_heard: Instant,
+    /// When we last sent a `Join` or `Watch`
+    last_hello: Option<Instant>,
+    sequence: u32,
+    /// The newest snapshot that hasn't been shown yet
+    latest: Option<Snapshot>,
+    /// The tick of the last snapshot we showed
+    shown_tick: Option<u32>,
+    /// Snapshots a spectator hasn't been shown yet, oldest first
+    buffer: VecDeque<Snapshot>,
+    /// The server tick a spectator is being shown
+    playback: Option<f32>,
+    /// How many people are watching, as of the last snapshot
+    spectators: u8,
+    /// How many ticks a second the server runs at, we tick along with it
+    tick_rate: u32,
+}
+
+impl ServerConnection {
+    /// Asks the server at `address` for a racket
+    pub fn connect(address: &str) -> io::Result<ServerConnection> {
+        ServerConnection::new(address, false)
+    }
+
+    /// Watches the match on the server, or the game hosting, at `address`
+    pub fn watch(address: &str) -> io::Result<ServerConnection> {
+        ServerConnection::new(address, true)
+    }
+
+    fn new(address: &str, spectating: bool) -> io::Result<ServerConnection> {
+        let server = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no address for {}", address),
+            )
+        })?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_nonblocking(true)?;
+        Ok(ServerConnection {
+            socket,
+            server,
+            status: Status::Connecting(server),
+            local_player: Player::One,
+            spectating,
+            last_heard: Instant::now(),
+            last_hello: None,
+            sequence: 0,
+            latest: None,
+            shown_tick: None,
+            buffer: VecDeque::new(),
+            playback: None,
+            spectators: 0,
+            tick_rate: TICK_RATE,
+        })
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// How many ticks a second the server runs the match at
+    pub fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    /// How many people are watching the match
+    pub fn spectators(&self) -> u8 {
+        self.spectators
+    }
+
+    fn send(&self, message: &ClientMessage) {
+        let _ = self.socket.send_to(&message.encode(), self.server);
+    }
+
+    /// Reads everything that has arrived and keeps the connection alive.
+    /// Gives back the match once the server has let us in.
+    pub fn poll(&mut self) -> Option<Simulation> {
+        let mut started = None;
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let size = match self.socket.recv_from(&mut buffer) {
+                Ok((size, from)) if from == self.server => size,
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            let message = match ServerMessage::decode(&buffer[..size]) {
+                Some(message) => message,
+                None => continue,
+            };
+            self.last_heard = Instant::now();
+            match message {
+                ServerMessage::Welcome {
+                    player,
+                    field_w,
+                    field_h,
+                    rules,
+                    tick_rate,
+                } => {
+                    if let (Status::Connecting(_), false) = (&self.status, self.spectating) {
+                        self.local_player = player;
+                        self.tick_rate = tick_rate;
+                        self.status = Status::WaitingForOpponent;
+                        started = Some(Simulation::new(field_w, field_h, rules));
+                    }
+                }
+                ServerMessage::Watching {
+                    field_w,
+                    field_h,
+                    rules,
+                    tick_rate,
+                } => {
+                    if let (Status::Connecting(_), true) = (&self.status, self.spectating) {
+                        self.tick_rate = tick_rate;
+                        self.status = Status::WaitingForOpponent;
+                        started = Some(Simulation::new(field_w, field_h, rules));
+                    }
+                }
+                ServerMessage::Full => {
+                    let reason = if self.spectating {
+                        "Too many people are watching already"
+                    } else {
+                        "The server is full"
+                    };
+                    self.status = Status::Disconnected(reason.to_string());
+                }
+                ServerMessage::Snapshot(snapshot) if self.spectating => {
+                    self.buffer_snapshot(snapshot)
+                }
+                ServerMessage::Snapshot(snapshot) => {
+                    if self
+                        .latest
+                        .map_or(true, |latest| snapshot.tick > latest.tick)
+                    {
+                        self.latest = Some(snapshot);
+                    }
+                }
+            }
+        }
+
+        let hello = match (&self.status, self.spectating) {
+            (Status::Connecting(_), false) => Some((ClientMessage::Join, JOIN_INTERVAL)),
+            (Status::Connecting(_), true) => Some((ClientMessage::Watch, JOIN_INTERVAL)),
+            (Status::Disconnected(_), _) => None,
+            // players keep the connection alive with their inputs, spectators have to say something
+            (_, true) => Some((ClientMessage::Watch, WATCH_INTERVAL)),
+            (_, false) => None,
+        };
+        if let Some((message, interval)) = hello {
+            if self
+                .last_hello
+                .map_or(true, |sent| sent.elapsed() >= interval)
+            {
+                self.send(&message);
+                self.last_hello = Some(Instant::now());
+            }
+        }
+        match self.status {
+            Status::Connecting(_) | Status::Disconnected(_) => (),
+            _ => {
+                if self.last_heard.elapsed() > TIMEOUT {
+                    self.status = Status::Disconnected("Lost the server".to_string());
+                }
+            }
+        }
+        started
+    }
+
+    /// Puts a snapshot in the spectator's buffer, in order of tick
+    fn buffer_snapshot(&mut self, snapshot: Snapshot) {
+        if self.shown_tick.is_some_and(|tick| snapshot.tick <= tick) {
+            return;
+        }
+        let index = self
+            .buffer
+            .partition_point(|other| other.tick < snapshot.tick);
+        if self
+            .buffer
+            .get(index)
+            .map_or(true, |other| other.tick != snapshot.tick)
+        {
+            self.buffer.insert(index, snapshot);
+        }
+    }
+
+    /// Sends our racket input for this tick
+    pub fn send_input(&mut self, axis: f32) {
+        self.sequence += 1;
+        self.send(&ClientMessage::Input {
+            sequence: self.sequence,
+            axis,
+        });
+    }
+
+    /// Shows the newest snapshot in `sim`, working out what happened since the last one.
+    /// Spectators get shown the match a little in the past instead. Meant to be
+    /// called once a tick, gives `None` if there's nothing new to show.
+    pub fn apply(&mut self, sim: &mut Simulation) -> Option<Vec<Event>> {
+        let snapshot = match self.next_snapshot() {
+            Some(snapshot) if self.shown_tick.map_or(true, |tick| snapshot.tick > tick) => snapshot,
+            _ => return None,
+        };
+        let mut events = Vec::new();
+        self.shown_tick = Some(snapshot.tick);
+        self.spectators = snapshot.spectators;
+        self.status = if snapshot.waiting {
+            Status::WaitingForOpponent
+        } else {
+            Status::Playing
+        };
+
+        if snapshot.last_hitter != sim.last_hitter {
+            if let Some(player) = snapshot.last_hitter {
+                events.push(Event::Hit(player));
+            }
+        }
+        for &player in &[Player::One, Player::Two] {
+            if snapshot.scores[player.index()] > sim.scores[player.index()]
+                || snapshot.games[player.index()] > sim.games[player.index()]
+            {
+                events.push(Event::Goal(player));
+            }
+        }
+        // the ball turning around vertically without anyone hitting it or scoring
+        // means it bounced off a wall somewhere between the snapshots
+        if events.is_empty() && snapshot.ball_vel[1] * sim.ball_vel.y < 0.0 {
+            events.push(Event::Wall);
+        }
+        if let (None, Some(winner)) = (sim.winner, snapshot.winner) {
+            events.push(Event::MatchWon(winner));
+        }
+
+        snapshot.apply(sim);
+        Some(events)
+    }
+
+    /// The snapshot to show this tick. Players always get the newest one, spectators
+    /// get whichever one is due, with playback running at the server's pace and
+    /// drifting a little faster or slower to stay `SPECTATOR_DELAY` behind.
+    fn next_snapshot(&mut self) -> Option<Snapshot> {
+        if !self.spectating {
+            return self.latest.take();
+        }
+        let newest = self.buffer.back()?.tick as f32;
+        let target = newest - SPECTATOR_DELAY;
+        let playback = match self.playback {
+            Some(playback) if (target - playback).abs() < SPECTATOR_MAX_DRIFT => {
+                playback + 1.0 + (target - playback) * SPECTATOR_CATCH_UP
+            }
+            _ => target,
+        };
+        self.playback = Some(playback);
+
+        let mut due = None;
+        while self
+            .buffer
+            .front()
+            .is_some_and(|snapshot| snapshot.tick as f32 <= playback)
+        {
+            due = self.buffer.pop_front();
+        }
+        due
+    }
+}
+
+impl Drop for ServerConnection {
+    fn drop(&mut self) {
+        self.send(&ClientMessage::Leave);
+    }
+}
@@ -0,0 +1,228 @@
+//! Recording matches so they can be watched again.
+//! A match only depends on its seed, rules, field and the inputs of every tick,
+//! so that's all a replay file holds. Playing those inputs back into a
+//! simulation with the same seed gives the exact same match.
+
+use crate::rules::Ruleset;
+use crate::simulation::{check_tick_rate, Input, Simulation, MAX_TICK_RATE};
+use crate::wire::{Reader, Writer};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Starts every replay file, so other files don't get mistaken for one
+const MAGIC: &[u8; 4] = b"RPRP";
+/// Bumped whenever the file layout or the simulation changes, old replays would play out differently
+const FORMAT_VERSION: u8 = 2;
+/// What replay files end in
+pub const EXTENSION: &str = "replay";
+/// The most ticks a replay can have, four hours at the fastest tick rate.
+/// Anything longer is a broken file and would only eat up memory.
+const MAX_TICKS: usize = 4 * 60 * 60 * MAX_TICK_RATE as usize;
+
+/// A recorded match
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+    pub field_w: f32,
+    pub field_h: f32,
+    pub rules: Ruleset,
+    /// How many ticks make a second, the same inputs at a different rate play out differently
+    pub tick_rate: u32,
+    /// What both players did, one entry per tick
+    pub inputs: Vec<Input>,
+}
+
+impl Replay {
+    /// Starts recording `sim`, which should be a match that hasn't been stepped yet
+    pub fn new(sim: &Simulation, tick_rate: u32) -> Replay {
+        Replay {
+            seed: sim.seed,
+            field_w: sim.field_w,
+            field_h: sim.field_h,
+            rules: sim.rules,
+            tick_rate,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Adds the input of the next tick
+    pub fn record(&mut self, input: Input) {
+        self.inputs.push(input);
+    }
+
+    /// The match as it was before the first tick
+    pub fn start(&self) -> Simulation {
+        Simulation::with_seed(self.field_w, self.field_h, self.rules, self.seed)
+    }
+
+    /// How many seconds of the match there are
+    pub fn duration(&self) -> f32 {
+        self.inputs.len() as f32 / self.tick_rate as f32
+    }
+
+    /// Packs the replay into bytes. Rackets are often held still or at full
+    /// speed for a while, so the inputs are stored as runs of the same input.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.bytes(MAGIC);
+        w.u8(FORMAT_VERSION);
+        w.u64(self.seed);
+        w.f32(self.field_w);
+        w.f32(self.field_h);
+        w.rules(&self.rules);
+        w.u32(self.tick_rate);
+        let mut runs = Vec::new();
+        for &input in &self.inputs {
+            match runs.last_mut() {
+                Some((count, last)) if *last == input => *count += 1,
+                _ => runs.push((1u32, input)),
+            }
+        }
+        w.u32(runs.len() as u32);
+        for (count, input) in runs {
+            w.u32(count);
+            w.f32(input.player_1);
+            w.f32(input.player_2);
+        }
+        w.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Replay> {
+        let mut r = Reader::new(bytes);
+        if r.bytes(MAGIC.len())? != MAGIC || r.u8()? != FORMAT_VERSION {
+            return None;
+        }
+        let mut replay = Replay {
+            seed: r.u64()?,
+            field_w: r.f32()?,
+            field_h: r.f32()?,
+            rules: r.rules()?,
+            tick_rate: r.u32()?,
+            inputs: Vec::new(),
+        };
+        let field_ok = |size: f32| size.is_finite() && size > 0.0;
+        if check_tick_rate(replay.tick_rate).is_err()
+            || !field_ok(replay.field_w)
+            || !field_ok(replay.field_h)
+        {
+            return None;
+        }
+        for _ in 0..r.u32()? {
+            let count = r.u32()? as usize;
+            if count > MAX_TICKS - replay.inputs.len() {
+                return None;
+            }
+            let input = Input {
+                player_1: r.f32()?,
+                player_2: r.f32()?,
+            };
+            replay.inputs.extend(std::iter::repeat(input).take(count));
+        }
+        Some(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.encode())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let bytes = fs::read(path)?;
+        Replay::decode(&bytes).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a replay this version can play", path.display()),
+            )
+        })
+    }
+
+    /// Where in `dir` to save a replay recorded right now. Named after the time
+    /// down to the millisecond, with a number added if that's taken too, so new
+    /// ones never overwrite old ones.
+    pub fn new_path(dir: &Path) -> PathBuf {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        let mut path = dir.join(format!("match-{}.{}", millis, EXTENSION));
+        let mut number = 2;
+        while path.exists() {
+            path = dir.join(format!("match-{}-{}.{}", millis, number, EXTENSION));
+            number += 1;
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{FIELD_H, FIELD_W, MIN_TICK_RATE};
+
+    fn replay() -> Replay {
+        let sim = Simulation::with_seed(FIELD_W, FIELD_H, Ruleset::default(), 7);
+        let mut replay = Replay::new(&sim, 60);
+        for tick in 0..100 {
+            replay.record(Input {
+                player_1: if tick < 40 { 1.0 } else { -0.5 },
+                player_2: 0.0,
+            });
+        }
+        replay
+    }
+
+    #[test]
+    fn decoding_gives_back_what_was_encoded() {
+        let replay = replay();
+        assert_eq!(Replay::decode(&replay.encode()), Some(replay));
+    }
+
+    #[test]
+    fn cut_off_file_is_not_a_replay() {
+        let bytes = replay().encode();
+        for len in 0..bytes.len() {
+            assert_eq!(Replay::decode(&bytes[..len]), None);
+        }
+    }
+
+    #[test]
+    fn run_longer_than_any_match_is_not_a_replay() {
+        let mut bytes = replay().encode();
+        // the count of the last run, which is followed by its two inputs
+        let count = bytes.len() - 12;
+        bytes[count..count + 4].copy_from_slice(&[0xff; 4]);
+        assert_eq!(Replay::decode(&bytes), None);
+    }
+
+    #[test]
+    fn tick_rate_has_to_be_playable() {
+        for &tick_rate in &[0, MIN_TICK_RATE - 1, MAX_TICK_RATE + 1, u32::MAX] {
+            let replay = Replay {
+                tick_rate,
+                ..replay()
+            };
+            assert_eq!(Replay::decode(&replay.encode()), None);
+        }
+        let fastest = Replay {
+            tick_rate: MAX_TICK_RATE,
+            ..replay()
+        };
+        assert_eq!(Replay::decode(&fastest.encode()), Some(fastest));
+    }
+
+    #[test]
+    fn field_has_to_have_a_size() {
+        for &size in &[0.0, -600.0, f32::NAN, f32::INFINITY] {
+            let wide = Replay {
+                field_w: size,
+                ..replay()
+            };
+            let tall = Replay {
+                field_h: size,
+                ..replay()
+            };
+            assert_eq!(Replay::decode(&wide.encode()), None);
+            assert_eq!(Replay::decode(&tall.encode()), None);
+        }
+    }
+}
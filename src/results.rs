@@ -0,0 +1,66 @@
+//! A log of finished matches in `results.toml` next to the replays, one
+//! `[[match]]` entry each, with the seed so any of them can be played again.
+
+use crate::config;
+use pong::simulation::Simulation;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const RESULTS_FILE: &str = "results.toml";
+
+#[derive(Serialize)]
+struct MatchResult {
+    /// Seconds since 1970 when the match ended
+    finished: u64,
+    winner: u32,
+    games: [u32; 2],
+    /// Points in the last game
+    scores: [u32; 2],
+    win_score: u32,
+    win_by_two: bool,
+    best_of: u32,
+    serve: String,
+    /// A string because TOML numbers stop at `i64::MAX`
+    seed: String,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    #[serde(rename = "match")]
+    entries: [MatchResult; 1],
+}
+
+/// Adds the result of a finished match to the log
+pub fn record(sim: &Simulation) -> io::Result<()> {
+    let winner = match sim.winner {
+        Some(winner) => winner,
+        None => return Ok(()),
+    };
+    let dir = config::data_dir()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+    let entry = Entry {
+        entries: [MatchResult {
+            finished: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            winner: winner.index() as u32 + 1,
+            games: sim.games,
+            scores: sim.scores,
+            win_score: sim.rules.win_score,
+            win_by_two: sim.rules.win_by_two,
+            best_of: sim.rules.best_of,
+            serve: sim.rules.serve.name().to_string(),
+            seed: sim.seed.to_string(),
+        }],
+    };
+    let text = toml::to_string(&entry).map_err(io::Error::other)?;
+    fs::create_dir_all(&dir)?;
+    // appending a table array entry to the end of the file keeps it valid TOML
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(RESULTS_FILE))?;
+    writeln!(file, "{}", text)
+}
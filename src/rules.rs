@@ -0,0 +1,141 @@
+//! How a match is played: how many points win a game, how many games win
+//! the match and who gets to serve.
+
+use crate::simulation::Player;
+
+/// Who serves the next ball
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServeRule {
+    /// The player who just lost the point serves
+    Loser,
+    /// The players take turns
+    Alternate,
+    /// The ball just goes in a random direction
+    Random,
+}
+
+impl ServeRule {
+    pub const ALL: [ServeRule; 3] = [ServeRule::Loser, ServeRule::Alternate, ServeRule::Random];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ServeRule::Loser => "loser serves",
+            ServeRule::Alternate => "alternating",
+            ServeRule::Random => "random",
+        }
+    }
+}
+
+/// How the ball leaves the middle when it's served. Every serve gets a random
+/// angle out of a range and a random speed around an average, so no two
+/// openings are quite the same.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ServeModel {
+    /// The flattest a serve can go, in radians away from straight across
+    pub min_angle: f32,
+    /// The steepest a serve can go, in radians away from straight across
+    pub max_angle: f32,
+    /// The average serve speed in pixels per second
+    pub speed: f32,
+    /// Serves can be this much faster or slower than `speed`, speeds near the
+    /// average come up more often than ones out at the edges
+    pub speed_spread: f32,
+    /// Serve towards whoever lost the last point, instead of the way `ServeRule` says
+    pub toward_conceder: bool,
+}
+
+impl Default for ServeModel {
+    fn default() -> ServeModel {
+        ServeModel {
+            min_angle: 15f32.to_radians(),
+            max_angle: 45f32.to_radians(),
+            // as fast as the old serves, which always went off at 45 degrees
+            speed: 270.0 * std::f32::consts::SQRT_2,
+            speed_spread: 40.0,
+            toward_conceder: false,
+        }
+    }
+}
+
+impl ServeModel {
+    /// Fixes up numbers that would break a serve, like a range the wrong way round
+    /// or serves that go straight up and never reach anyone
+    pub fn clamped(self) -> ServeModel {
+        let limit = 75f32.to_radians();
+        let clean = |value: f32, default: f32| if value.is_finite() { value } else { default };
+        let default = ServeModel::default();
+        let min_angle = clean(self.min_angle, default.min_angle).clamp(0.0, limit);
+        let max_angle = clean(self.max_angle, default.max_angle).clamp(min_angle, limit);
+        let speed = clean(self.speed, default.speed).clamp(50.0, 2000.0);
+        ServeModel {
+            min_angle,
+            max_angle,
+            speed,
+            speed_spread: clean(self.speed_spread, 0.0).clamp(0.0, speed * 0.5),
+            toward_conceder: self.toward_conceder,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ruleset {
+    /// Points needed to win a game
+    pub win_score: u32,
+    /// A game has to be won by two clear points
+    pub win_by_two: bool,
+    /// How many games the match can last, the first to win more than half wins it
+    pub best_of: u32,
+    pub serve: ServeRule,
+    /// Seconds the ball waits in the middle before each serve
+    pub serve_countdown: f32,
+    pub serve_model: ServeModel,
+}
+
+impl Default for Ruleset {
+    fn default() -> Ruleset {
+        Ruleset {
+            win_score: 11,
+            win_by_two: true,
+            best_of: 1,
+            serve: ServeRule::Random,
+            serve_countdown: 2.0,
+            serve_model: ServeModel::default(),
+        }
+    }
+}
+
+impl Ruleset {
+    /// Checks if either player has won the game with these points
+    pub fn game_winner(&self, points: [u32; 2]) -> Option<Player> {
+        let lead_needed = if self.win_by_two { 2 } else { 1 };
+        for &player in &[Player::One, Player::Two] {
+            let mine = points[player.index()];
+            let theirs = points[player.other().index()];
+            if mine >= self.win_score && mine >= theirs + lead_needed {
+                return Some(player);
+            }
+        }
+        None
+    }
+
+    /// Checks if either player has won enough games to take the match
+    pub fn match_winner(&self, games: [u32; 2]) -> Option<Player> {
+        let games_needed = self.best_of / 2 + 1;
+        if games[0] >= games_needed {
+            Some(Player::One)
+        } else if games[1] >= games_needed {
+            Some(Player::Two)
+        } else {
+            None
+        }
+    }
+
+    /// Who serves after `scorer` won a point that `server` served
+    pub fn next_server(&self, server: Player, scorer: Player) -> Player {
+        match self.serve {
+            ServeRule::Loser => scorer.other(),
+            ServeRule::Alternate => server.other(),
+            ServeRule::Random => server,
+        }
+    }
+}
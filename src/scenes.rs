@@ -0,0 +1,175 @@
+//! The screens the game can be on and the menus on them.
+//! Scenes live on a stack so that e.g. the options screen can be opened
+//! from both the title screen and the pause menu and go back to the right one.
+
+use crate::controls::Control;
+
+/// A screen of the game
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scene {
+    Title,
+    Options,
+    Controls,
+    Playing,
+    Paused,
+    GameOver,
+    /// Waiting for the other side of an online match
+    Connecting,
+    /// The games found on the local network
+    Lobby,
+}
+
+/// What picking a menu entry does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Play,
+    Resume,
+    Restart,
+    Options,
+    Lobby,
+    /// Host a game for someone on the network to join
+    HostGame,
+    /// Join the game at this position in the lobby's list
+    JoinGame(usize),
+    ToTitle,
+    Back,
+    Quit,
+    Player1,
+    Player2,
+    MouseSpeed1,
+    MouseSpeed2,
+    Serve,
+    /// Serve at whoever lost the last point
+    ServeToConceder,
+    WinScore,
+    WinByTwo,
+    BestOf,
+    /// The light and shadow quality
+    Lighting,
+    Controls,
+    /// Waits for a key press and binds it to the control
+    Rebind(Control),
+    ResetControls,
+}
+
+impl Scene {
+    /// The big text at the top of the menu
+    pub fn heading(self) -> &'static str {
+        match self {
+            Scene::Title => "Rusty Pong",
+            Scene::Options => "Options",
+            Scene::Controls => "Controls",
+            Scene::Playing => "",
+            Scene::Paused => "Paused",
+            Scene::GameOver => "Game Over",
+            Scene::Connecting => "Online",
+            Scene::Lobby => "LAN games",
+        }
+    }
+
+    /// The menu entries, top to bottom.
+    /// The lobby also lists the games it has found above these.
+    pub fn actions(self) -> &'static [Action] {
+        match self {
+            Scene::Title => &[Action::Play, Action::Lobby, Action::Options, Action::Quit],
+            Scene::Options => &[
+                Action::Player1,
+                Action::Player2,
+                Action::MouseSpeed1,
+                Action::MouseSpeed2,
+                Action::Serve,
+                Action::ServeToConceder,
+                Action::WinScore,
+                Action::WinByTwo,
+                Action::BestOf,
+                Action::Lighting,
+                Action::Controls,
+                Action::Back,
+            ],
+            Scene::Controls => &[
+                Action::Rebind(Control::P1Up),
+                Action::Rebind(Control::P1Down),
+                Action::Rebind(Control::P2Up),
+                Action::Rebind(Control::P2Down),
+                Action::Rebind(Control::Pause),
+                Action::Rebind(Control::Camera),
+                Action::Rebind(Control::Debug),
+                Action::Rebind(Control::Fullscreen),
+                Action::Rebind(Control::MenuUp),
+                Action::Rebind(Control::MenuDown),
+                Action::Rebind(Control::MenuLeft),
+                Action::Rebind(Control::MenuRight),
+                Action::Rebind(Control::Confirm),
+                Action::Rebind(Control::Back),
+                Action::ResetControls,
+                Action::Back,
+            ],
+            Scene::Playing => &[],
+            Scene::Paused => &[
+                Action::Resume,
+                Action::Restart,
+                Action::Options,
+                Action::ToTitle,
+            ],
+            Scene::GameOver => &[Action::Restart, Action::ToTitle, Action::Quit],
+            Scene::Connecting => &[Action::Back],
+            Scene::Lobby => &[Action::HostGame, Action::Back],
+        }
+    }
+}
+
+/// The scenes that are open, the last one is the one on screen.
+/// Every scene remembers which menu entry was selected on it.
+pub struct SceneStack {
+    scenes: Vec<(Scene, usize)>,
+}
+
+impl SceneStack {
+    pub fn new(scene: Scene) -> SceneStack {
+        SceneStack {
+            scenes: vec![(scene, 0)],
+        }
+    }
+
+    pub fn top(&self) -> Scene {
+        self.scenes.last().map_or(Scene::Title, |&(scene, _)| scene)
+    }
+
+    pub fn selected(&self) -> usize {
+        self.scenes.last().map_or(0, |&(_, selected)| selected)
+    }
+
+    pub fn push(&mut self, scene: Scene) {
+        self.scenes.push((scene, 0));
+    }
+
+    /// Goes back to the scene below, the bottom scene never gets popped
+    pub fn pop(&mut self) {
+        if self.scenes.len() > 1 {
+            self.scenes.pop();
+        }
+    }
+
+    /// Throws away the whole stack and starts over from `scene`
+    pub fn replace(&mut self, scene: Scene) {
+        self.scenes.clear();
+        self.push(scene);
+    }
+
+    /// Moves the menu selection up (negative) or down (positive) in a menu
+    /// `count` entries long, wrapping around
+    pub fn move_selection(&mut self, delta: isize, count: usize) {
+        if let Some((_, selected)) = self.scenes.last_mut() {
+            if count > 0 {
+                *selected = (*selected as isize + delta).rem_euclid(count as isize) as usize;
+            }
+        }
+    }
+
+    /// Keeps the selection inside a menu that just got shorter
+    pub fn clamp_selection(&mut self, count: usize) {
+        if let Some((_, selected)) = self.scenes.last_mut() {
+            *selected = (*selected).min(count.saturating_sub(1));
+        }
+    }
+}
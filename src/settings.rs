@@ -0,0 +1,349 @@
+//! Settings that aren't in the menus, read from `settings.toml` in the config directory.
+//! The game only writes it back to remember the window size, fullscreen, lighting
+//! and who plays with the mouse.
+
+use crate::args::Args;
+use crate::config;
+use crate::lighting::{Quality, MAX_LIGHTS};
+use ggez::conf::{FullscreenType, NumSamples, WindowMode, WindowSetup};
+use pong::opponent::ControllerKind;
+use pong::rules::ServeModel;
+use pong::simulation::{FIELD_H, FIELD_W};
+use serde::{Deserialize, Serialize};
+
+const CONFIG_FILE: &str = "settings.toml";
+/// The smallest window the field is still readable in
+const MIN_WINDOW_W: f32 = 320.0;
+const MIN_WINDOW_H: f32 = 240.0;
+/// Fewer rays than this and shadows turn into blocks
+const MIN_LIGHT_RAYS: u16 = 64;
+/// More than this and some GPUs run out of room for the shadow map
+const MAX_LIGHT_RAYS: u16 = 2048;
+/// Room for this many particles is set aside when the game starts
+const MAX_PARTICLES: u16 = 10000;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Every match starts from this seed instead of a random one,
+    /// so serves come out the same each time. `--seed` overrides it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// How many times a second the game logic runs, from 30 to 240.
+    /// `--tick-rate` overrides it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick_rate: Option<u32>,
+    pub serve: ServeSettings,
+    pub players: PlayersSettings,
+    pub graphics: GraphicsSettings,
+    pub post_processing: PostProcessSettings,
+    /// The file is there but couldn't be read, so it's left alone rather than
+    /// overwritten with the defaults and whatever the player fixes in it lost
+    #[serde(skip)]
+    unreadable: bool,
+}
+
+/// The `[serve]` table, how serves leave the middle. Angles are in degrees
+/// away from straight across, speeds in pixels per second.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServeSettings {
+    pub min_angle: f32,
+    pub max_angle: f32,
+    pub speed: f32,
+    pub speed_spread: f32,
+    pub toward_conceder: bool,
+}
+
+impl Default for ServeSettings {
+    fn default() -> ServeSettings {
+        let model = ServeModel::default();
+        // whole degrees, or the file gets written with 15.000001
+        ServeSettings {
+            min_angle: model.min_angle.to_degrees().round(),
+            max_angle: model.max_angle.to_degrees().round(),
+            speed: model.speed,
+            speed_spread: model.speed_spread,
+            toward_conceder: model.toward_conceder,
+        }
+    }
+}
+
+impl ServeSettings {
+    pub fn model(&self) -> ServeModel {
+        ServeModel {
+            min_angle: self.min_angle.to_radians(),
+            max_angle: self.max_angle.to_radians(),
+            speed: self.speed,
+            speed_spread: self.speed_spread,
+            toward_conceder: self.toward_conceder,
+        }
+        .clamped()
+    }
+}
+
+/// The `[players]` table, how each racket is played when the game starts
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlayersSettings {
+    pub player_1: PlayerSettings,
+    pub player_2: PlayerSettings,
+}
+
+impl PlayersSettings {
+    /// Player 1's for side 0, player 2's for side 1
+    pub fn side_mut(&mut self, side: usize) -> &mut PlayerSettings {
+        match side {
+            0 => &mut self.player_1,
+            _ => &mut self.player_2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlayerSettings {
+    /// The racket follows the mouse instead of the keys
+    pub mouse: bool,
+    /// Top speed of the racket following the mouse, from 0 to 1 of a racket's full speed
+    pub mouse_speed: f32,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> PlayerSettings {
+        PlayerSettings {
+            mouse: false,
+            mouse_speed: 0.75,
+        }
+    }
+}
+
+impl PlayerSettings {
+    /// The mouse if that's what was picked, otherwise `kind`
+    pub fn controller_kind(&self, kind: ControllerKind) -> ControllerKind {
+        if self.mouse {
+            ControllerKind::Mouse
+        } else {
+            kind
+        }
+    }
+
+    /// `mouse_speed` kept between standing still and full speed
+    pub fn mouse_speed(&self) -> f32 {
+        if self.mouse_speed.is_nan() {
+            return PlayerSettings::default().mouse_speed;
+        }
+        self.mouse_speed.clamp(0.0, 1.0)
+    }
+}
+
+/// The `[graphics]` table, how the window looks. The command line can override
+/// all of it for one run.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    /// Size of the window when it isn't fullscreen
+    pub width: f32,
+    pub height: f32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    /// Samples per pixel for smoothing edges: 1, 2, 4, 8 or 16
+    pub msaa: u32,
+    pub title: String,
+    /// How the light and shadows look, "off", "low", "medium" or "high"
+    pub lighting: Quality,
+    /// How many directions the light checks for shadows, instead of what `lighting` picks.
+    /// Increasing this number will result in better quality shadows. If you
+    /// increase too much you might hit some GPU shader hardware limits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light_rays: Option<u16>,
+    /// The most lights drawn in a frame, the ball's comes first and then flashes and racket glows
+    pub max_lights: u8,
+    /// The most particles flying around at once, 0 turns them off
+    pub max_particles: u16,
+    /// Sparks and bursts light up what's around them, after the other lights get theirs
+    pub particle_lights: bool,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> GraphicsSettings {
+        GraphicsSettings {
+            width: FIELD_W,
+            height: FIELD_H,
+            fullscreen: false,
+            vsync: true,
+            msaa: 1,
+            title: "Rusty Pong".to_string(),
+            lighting: Quality::Medium,
+            light_rays: None,
+            max_lights: 4,
+            max_particles: 1000,
+            particle_lights: true,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    /// These settings with whatever the command line changed
+    pub fn with_args(&self, args: &Args) -> GraphicsSettings {
+        let mut graphics = self.clone();
+        if let Some((width, height)) = args.window_size {
+            graphics.width = width;
+            graphics.height = height;
+        }
+        if let Some(fullscreen) = args.fullscreen {
+            graphics.fullscreen = fullscreen;
+        }
+        if let Some(vsync) = args.vsync {
+            graphics.vsync = vsync;
+        }
+        if let Some(msaa) = args.msaa {
+            graphics.msaa = msaa;
+        }
+        if let Some(title) = &args.title {
+            graphics.title = title.clone();
+        }
+        if let Some(lighting) = args.lighting {
+            graphics.lighting = lighting;
+        }
+        if let Some(light_rays) = args.light_rays {
+            graphics.light_rays = Some(light_rays);
+        }
+        if let Some(max_lights) = args.max_lights {
+            graphics.max_lights = max_lights;
+        }
+        graphics
+    }
+
+    pub fn window_setup(&self) -> WindowSetup {
+        let samples = NumSamples::from_u32(self.msaa).unwrap_or_else(|| {
+            eprintln!("{} samples of msaa isn't possible, using none", self.msaa);
+            NumSamples::One
+        });
+        WindowSetup::default()
+            .title(&self.title)
+            .vsync(self.vsync)
+            .samples(samples)
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        let fullscreen_type = if self.fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Windowed
+        };
+        WindowMode::default()
+            .dimensions(self.width.max(MIN_WINDOW_W), self.height.max(MIN_WINDOW_H))
+            .min_dimensions(MIN_WINDOW_W, MIN_WINDOW_H)
+            .fullscreen_type(fullscreen_type)
+            .resizable(true)
+    }
+
+    /// How many rays the light casts, kept to what GPUs can handle
+    pub fn light_rays(&self) -> u16 {
+        self.light_rays
+            .unwrap_or_else(|| self.lighting.rays())
+            .clamp(MIN_LIGHT_RAYS, MAX_LIGHT_RAYS)
+    }
+
+    /// `max_lights` with at least the ball's light and not more than the shaders have room for
+    pub fn max_lights(&self) -> usize {
+        (self.max_lights as usize).clamp(1, MAX_LIGHTS)
+    }
+
+    /// `max_particles` kept to what's sensible to set aside
+    pub fn max_particles(&self) -> usize {
+        self.max_particles.min(MAX_PARTICLES) as usize
+    }
+}
+
+/// The `[post_processing]` table, effects laid over the whole picture.
+/// They're applied in the order they're listed here, all off by default.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PostProcessSettings {
+    /// Lights glow into whatever is around them, needs lighting on
+    pub bloom: bool,
+    /// How bright the glow is compared to the light itself
+    pub bloom_strength: f32,
+    /// How far the glow spreads
+    pub bloom_radius: f32,
+    /// Colors split apart towards the edges like through a cheap lens
+    pub chromatic_aberration: bool,
+    /// How many pixels apart they are in the corners
+    pub chromatic_aberration_pixels: f32,
+    /// Bent glass and dark lines between the rows of pixels like an old tube TV
+    pub crt: bool,
+    pub crt_curvature: f32,
+    /// How dark the lines between rows are, from 0 to 1
+    pub scanlines: f32,
+    /// Darker corners
+    pub vignette: bool,
+    /// How dark the very edge gets, from 0 to 1
+    pub vignette_strength: f32,
+    /// Everything gets multiplied by this, 1 leaves the picture alone
+    pub brightness: f32,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> PostProcessSettings {
+        PostProcessSettings {
+            bloom: false,
+            bloom_strength: 0.8,
+            bloom_radius: 2.0,
+            chromatic_aberration: false,
+            chromatic_aberration_pixels: 2.0,
+            crt: false,
+            crt_curvature: 0.08,
+            scanlines: 0.3,
+            vignette: false,
+            vignette_strength: 0.4,
+            brightness: 1.0,
+        }
+    }
+}
+
+impl PostProcessSettings {
+    /// Whether any of the effects change the picture at all
+    pub fn enabled(&self) -> bool {
+        self.bloom
+            || self.chromatic_aberration
+            || self.crt
+            || self.vignette
+            || self.brightness != 1.0
+    }
+
+    /// These settings with every number kept to what makes sense
+    pub fn clamped(&self) -> PostProcessSettings {
+        PostProcessSettings {
+            bloom_strength: self.bloom_strength.max(0.0),
+            bloom_radius: self.bloom_radius.clamp(0.0, 8.0),
+            chromatic_aberration_pixels: self.chromatic_aberration_pixels.clamp(0.0, 32.0),
+            crt_curvature: self.crt_curvature.clamp(0.0, 1.0),
+            scanlines: self.scanlines.clamp(0.0, 1.0),
+            vignette_strength: self.vignette_strength.clamp(0.0, 1.0),
+            brightness: self.brightness.max(0.0),
+            ..self.clone()
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings, anything missing gets the default
+    pub fn load() -> Settings {
+        config::load(CONFIG_FILE).unwrap_or_else(|| Settings {
+            unreadable: config::exists(CONFIG_FILE),
+            ..Settings::default()
+        })
+    }
+
+    pub fn save(&self) {
+        if self.unreadable {
+            eprintln!("not saving {}, it couldn't be read", CONFIG_FILE);
+            return;
+        }
+        if let Err(e) = config::save(CONFIG_FILE, self) {
+            eprintln!("could not save {}: {}", CONFIG_FILE, e);
+        }
+    }
+}
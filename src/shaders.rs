@@ -0,0 +1,84 @@
+//! Shader sources live in the resources directory so they can be changed
+//! without building the game again. A copy of every one is built into the game
+//! too, for when the file is missing or broken.
+//!
+//! With `--dev` the game keeps an eye on the files and swaps in the new shaders
+//! as soon as one is saved, showing what's wrong on screen if it doesn't compile.
+
+use ggez::filesystem;
+use ggez::Context;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the watcher looks at the files
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The source of the shader in `file`, or `embedded` if it can't be read
+pub fn source(ctx: &mut Context, file: &str, embedded: &'static str) -> String {
+    match read(ctx, file) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("could not read {}, using the built in one: {}", file, e);
+            embedded.to_string()
+        }
+    }
+}
+
+/// The source of the shader in `file`, from the resources directory
+pub fn read(ctx: &mut Context, file: &str) -> Result<String, String> {
+    let mut source = String::new();
+    filesystem::open(ctx, file)
+        .map_err(|e| e.to_string())?
+        .read_to_string(&mut source)
+        .map_err(|e| e.to_string())?;
+    Ok(source)
+}
+
+/// Notices when shader files are saved
+pub struct Watcher {
+    /// Every file and when it was last changed
+    files: Vec<(PathBuf, Option<SystemTime>)>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    /// Watches `files` in the resources directory `dir`
+    pub fn new(dir: &Path, files: &[&str]) -> Watcher {
+        let files = files
+            .iter()
+            .map(|file| {
+                let path = dir.join(file.trim_start_matches('/'));
+                let modified = modified(&path);
+                (path, modified)
+            })
+            .collect();
+        Watcher {
+            files,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Whether any of the files changed since the last time this said so
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let mut changed = false;
+        for (path, last_modified) in &mut self.files {
+            let modified = modified(path);
+            if modified != *last_modified {
+                *last_modified = modified;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
@@ -0,0 +1,712 @@
+//! All the actual pong logic lives in here.
+//! Nothing in this module touches the window, so it can be stepped
+//! and tested without a GPU.
+
+use crate::rules::{Ruleset, ServeModel, ServeRule};
+use ggez::nalgebra as na;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+///Width of the field every match is played on, the window shows it letterboxed
+pub const FIELD_W: f32 = 800.0;
+///Height of the field every match is played on
+pub const FIELD_H: f32 = 600.0;
+///Padding between the rackets and the edge of the screen
+pub const PADDING: f32 = 10.0;
+///Height of a racket
+pub const RACKET_HEIGHT: f32 = 100.0;
+///Widthe of a racket
+pub const RACKET_WIDTH: f32 = 10.0;
+///Racket height devided by two
+pub const RACKET_HEIGHT_HALF: f32 = RACKET_HEIGHT * 0.5;
+///Racket width devided by two
+pub const RACKET_WIDTH_HALF: f32 = RACKET_WIDTH * 0.5;
+///Diameter of the ball
+pub const BALL_SIZE: f32 = 10.0;
+///Radius of the ball
+pub const BALL_SIZE_HALF: f32 = BALL_SIZE * 0.5;
+///speed of the player racket
+pub const PLAYER_SPEED: f32 = 600.0;
+///how much faster the ball gets every time it hits a racket
+pub const BALL_SPEEDUP: f32 = 30.0;
+///steepest angle (in radians) the ball can leave a racket at
+pub const MAX_BOUNCE_ANGLE: f32 = std::f32::consts::PI / 3.0;
+///how much the racket moving up or down at full speed changes the bounce angle,
+///where 1.0 is as much as hitting the very edge of the racket
+pub const RACKET_SPIN: f32 = 0.3;
+///how many things the ball can bounce off in a single step before we give up
+const MAX_BOUNCES_PER_STEP: usize = 8;
+///how many times a second the game logic runs unless told otherwise
+pub const TICK_RATE: u32 = 120;
+///fewer ticks than this and the rackets and ball move in visible jumps
+pub const MIN_TICK_RATE: u32 = 30;
+///more than this leaves slower machines no time between ticks to keep up
+pub const MAX_TICK_RATE: u32 = 240;
+
+/// Checks that the game logic can run `tick_rate` times a second
+pub fn check_tick_rate(tick_rate: u32) -> Result<u32, String> {
+    if (MIN_TICK_RATE..=MAX_TICK_RATE).contains(&tick_rate) {
+        Ok(tick_rate)
+    } else {
+        Err(format!(
+            "the tick rate has to be between {} and {}, not {}",
+            MIN_TICK_RATE, MAX_TICK_RATE, tick_rate
+        ))
+    }
+}
+
+/// Clamps a value between a set amount of values
+pub fn clamp(value: &mut f32, low: f32, high: f32) {
+    if *value < low {
+        *value = low;
+    } else if *value > high {
+        *value = high;
+    }
+}
+
+/// Keeps a racket's y inside the field
+pub fn clamp_racket_y(y: &mut f32, field_h: f32) {
+    clamp(y, RACKET_HEIGHT_HALF, field_h - RACKET_HEIGHT_HALF);
+}
+
+/// Moves the racket up and down, `y_dir` of 1.0 is full speed upwards
+fn move_racket(pos: &mut na::Point2<f32>, y_dir: f32, field_h: f32, dt: f32) {
+    pos.y -= PLAYER_SPEED * dt * y_dir;
+    clamp_racket_y(&mut pos.y, field_h);
+}
+
+/// Picks how a serve leaves the middle out of what `model` allows.
+/// `dir_x` is 1.0 to serve to the right and -1.0 to the left.
+fn serve_velocity(rng: &mut StdRng, model: &ServeModel, dir_x: f32) -> na::Vector2<f32> {
+    let angle = if model.max_angle > model.min_angle {
+        rng.gen_range(model.min_angle, model.max_angle)
+    } else {
+        model.min_angle
+    };
+    let dir_y = if rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+    // the difference of two even rolls lands near zero more often than at the edges
+    let spread = model.speed_spread * (rng.gen::<f32>() - rng.gen::<f32>());
+    let speed = model.speed + spread;
+    na::Vector2::new(dir_x * speed * angle.cos(), dir_y * speed * angle.sin())
+}
+
+/// Checks if the ball overlaps a racket
+fn intersects_racket(ball_pos: na::Point2<f32>, racket_pos: na::Point2<f32>) -> bool {
+    ball_pos.x - BALL_SIZE_HALF < racket_pos.x + RACKET_WIDTH_HALF
+        && ball_pos.x + BALL_SIZE_HALF > racket_pos.x - RACKET_WIDTH_HALF
+        && ball_pos.y - BALL_SIZE_HALF < racket_pos.y + RACKET_HEIGHT_HALF
+        && ball_pos.y + BALL_SIZE_HALF > racket_pos.y - RACKET_HEIGHT_HALF
+}
+
+/// Sweeps the ball along `motion` and finds the first time it touches a racket.
+/// The racket is grown by the radius of the ball so we only have to trace a point
+/// against a rectangle. Returns how far along `motion` the hit happened (0.0 to 1.0)
+/// and the normal of the racket side that got hit.
+fn sweep_racket(
+    ball_pos: na::Point2<f32>,
+    motion: na::Vector2<f32>,
+    racket_pos: na::Point2<f32>,
+) -> Option<(f32, na::Vector2<f32>)> {
+    let half_size = na::Vector2::new(
+        RACKET_WIDTH_HALF + BALL_SIZE_HALF,
+        RACKET_HEIGHT_HALF + BALL_SIZE_HALF,
+    );
+    let min = racket_pos - half_size;
+    let max = racket_pos + half_size;
+
+    let mut t_enter = 0.0f32;
+    let mut t_exit = 1.0f32;
+    let mut normal = na::Vector2::zeros();
+
+    for axis in 0..2 {
+        if motion[axis] == 0.0 {
+            // moving parallel to this side, so we have to already be within it
+            if ball_pos[axis] <= min[axis] || ball_pos[axis] >= max[axis] {
+                return None;
+            }
+            continue;
+        }
+
+        let mut t0 = (min[axis] - ball_pos[axis]) / motion[axis];
+        let mut t1 = (max[axis] - ball_pos[axis]) / motion[axis];
+        let mut side = -1.0;
+        if t0 > t1 {
+            std::mem::swap(&mut t0, &mut t1);
+            side = 1.0;
+        }
+        if t0 >= t_enter {
+            t_enter = t0;
+            normal = na::Vector2::zeros();
+            normal[axis] = side;
+        }
+        t_exit = t_exit.min(t1);
+        if t_enter > t_exit {
+            return None;
+        }
+    }
+
+    // no normal means we started inside the racket, and moving away from
+    // a side we are touching is not a hit either
+    if normal == na::Vector2::zeros() || motion.dot(&normal) >= 0.0 {
+        return None;
+    }
+    Some((t_enter, normal))
+}
+
+/// Something the ball can bump into
+#[derive(Clone, Copy, Debug)]
+enum Contact {
+    Wall(f32),
+    Racket(Player, na::Vector2<f32>),
+}
+
+/// The two sides of the field, player 1 is on the left
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+impl Player {
+    /// Index into per-player arrays like `Simulation::scores`
+    pub fn index(self) -> usize {
+        match self {
+            Player::One => 0,
+            Player::Two => 1,
+        }
+    }
+
+    pub fn other(self) -> Player {
+        match self {
+            Player::One => Player::Two,
+            Player::Two => Player::One,
+        }
+    }
+}
+
+/// What the players are doing with their rackets for a single step.
+/// 1.0 moves the racket up at full speed and -1.0 moves it down.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Input {
+    pub player_1: f32,
+    pub player_2: f32,
+}
+
+/// Things that happened during a step that the renderer might care about
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// The ball bounced off this player's racket
+    Hit(Player),
+    /// The ball bounced off the top or bottom of the field
+    Wall,
+    /// This player scored a point
+    Goal(Player),
+    /// The countdown finished and the ball is on its way
+    Serve,
+    /// This player won a game
+    GameWon(Player),
+    /// This player won the whole match
+    MatchWon(Player),
+}
+
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    pub field_w: f32,
+    pub field_h: f32,
+    pub player_1_pos: na::Point2<f32>,
+    pub player_2_pos: na::Point2<f32>,
+    /// How fast the rackets moved vertically last step, positive is down like the y axis
+    pub player_1_vel: f32,
+    pub player_2_vel: f32,
+    pub ball_pos: na::Point2<f32>,
+    pub ball_vel: na::Vector2<f32>,
+    pub rules: Ruleset,
+    /// Points in the current game, player 1 first
+    pub scores: [u32; 2],
+    /// Games won so far in the match
+    pub games: [u32; 2],
+    /// Who serves the next ball
+    pub server: Player,
+    /// Seconds left before the ball is served, it sits in the middle until then
+    pub serve_timer: f32,
+    /// Set once someone has won the match, nothing moves after that
+    pub winner: Option<Player>,
+    /// Who touched the ball last, it keeps their colour until the other player hits it
+    pub last_hitter: Option<Player>,
+    /// Who lost the last point, nobody has at the start of a match
+    pub conceded: Option<Player>,
+    /// What the random number generator started from, the same seed and inputs
+    /// always play out the same match
+    pub seed: u64,
+    rng: StdRng,
+}
+
+impl Simulation {
+    /// Starts a match from a random seed
+    pub fn new(field_w: f32, field_h: f32, rules: Ruleset) -> Simulation {
+        Simulation::with_seed(field_w, field_h, rules, rand::random())
+    }
+
+    pub fn with_seed(field_w: f32, field_h: f32, rules: Ruleset, seed: u64) -> Simulation {
+        let (field_w_half, field_h_half) = (field_w * 0.5, field_h * 0.5);
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let server = match rng.gen_bool(0.5) {
+            true => Player::One,
+            false => Player::Two,
+        };
+
+        Simulation {
+            field_w,
+            field_h,
+            player_1_pos: na::Point2::new(RACKET_WIDTH_HALF + PADDING, field_h_half),
+            player_2_pos: na::Point2::new(field_w - RACKET_WIDTH_HALF - PADDING, field_h_half),
+            player_1_vel: 0.0,
+            player_2_vel: 0.0,
+            ball_pos: na::Point2::new(field_w_half, field_h_half),
+            ball_vel: na::Vector2::zeros(),
+            rules,
+            scores: [0, 0],
+            games: [0, 0],
+            server,
+            serve_timer: rules.serve_countdown,
+            winner: None,
+            last_hitter: None,
+            conceded: None,
+            seed,
+            rng,
+        }
+    }
+
+    /// A hash of everything that matters about the game right now,
+    /// two simulations that have stayed in sync give the same checksum
+    pub fn checksum(&self) -> u64 {
+        // FNV-1a, it only has to be the same on both ends and cheap enough to run often
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |value: u32| {
+            for byte in &value.to_le_bytes() {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for value in &[
+            self.player_1_pos.y,
+            self.player_2_pos.y,
+            self.ball_pos.x,
+            self.ball_pos.y,
+            self.ball_vel.x,
+            self.ball_vel.y,
+            self.serve_timer,
+        ] {
+            feed(value.to_bits());
+        }
+        for &value in self.scores.iter().chain(self.games.iter()) {
+            feed(value);
+        }
+        feed(self.server.index() as u32);
+        feed(self.winner.map_or(0, |winner| winner.index() as u32 + 1));
+        for player in &[self.last_hitter, self.conceded] {
+            feed(player.map_or(0, |player| player.index() as u32 + 1));
+        }
+        hash
+    }
+
+    /// Puts the ball back in the middle to wait for the next serve
+    fn reset_ball(&mut self) {
+        self.ball_pos.x = self.field_w * 0.5;
+        self.ball_pos.y = self.field_h * 0.5;
+        self.ball_vel = na::Vector2::zeros();
+        self.serve_timer = self.rules.serve_countdown;
+    }
+
+    /// Sends the ball off from the middle, away from whoever is serving
+    /// or towards whoever conceded, depending on the rules
+    fn serve(&mut self) {
+        let random_dir = if self.rng.gen_bool(0.5) { 1.0 } else { -1.0 };
+        let model = self.rules.serve_model;
+        let toward = match (model.toward_conceder, self.conceded, self.rules.serve) {
+            (true, Some(conceded), _) => Some(conceded),
+            (_, _, ServeRule::Random) => None,
+            _ => Some(self.server.other()),
+        };
+        // player 1 is on the left
+        let dir_x = match toward {
+            Some(Player::One) => -1.0,
+            Some(Player::Two) => 1.0,
+            None => random_dir,
+        };
+        self.ball_vel = serve_velocity(&mut self.rng, &model, dir_x);
+    }
+
+    /// Gives `scorer` a point and works out if that won them a game or the match
+    fn score_point(&mut self, scorer: Player, events: &mut Vec<Event>) {
+        self.scores[scorer.index()] += 1;
+        self.conceded = Some(scorer.other());
+        events.push(Event::Goal(scorer));
+        self.reset_ball();
+
+        if let Some(game_winner) = self.rules.game_winner(self.scores) {
+            self.games[game_winner.index()] += 1;
+            events.push(Event::GameWon(game_winner));
+            if let Some(match_winner) = self.rules.match_winner(self.games) {
+                self.winner = Some(match_winner);
+                events.push(Event::MatchWon(match_winner));
+                return;
+            }
+            self.scores = [0, 0];
+        }
+
+        self.server = self.rules.next_server(self.server, scorer);
+    }
+
+    /// Where a player's racket is
+    pub fn racket_pos(&self, player: Player) -> na::Point2<f32> {
+        match player {
+            Player::One => self.player_1_pos,
+            Player::Two => self.player_2_pos,
+        }
+    }
+
+    /// Sends the ball back from the front of a racket, a bit faster than it came.
+    /// Hitting it with the edge of the racket, or while the racket is moving,
+    /// sends it off at a steeper angle so players can aim.
+    fn bounce_off_racket(&mut self, player: Player, events: &mut Vec<Event>) {
+        let (racket_pos, racket_vel, dir_x) = match player {
+            Player::One => (self.player_1_pos, self.player_1_vel, 1.0),
+            Player::Two => (self.player_2_pos, self.player_2_vel, -1.0),
+        };
+
+        // -1.0 is the very top of the racket and 1.0 the very bottom
+        let mut offset = (self.ball_pos.y - racket_pos.y) / (RACKET_HEIGHT_HALF + BALL_SIZE_HALF);
+        offset += racket_vel / PLAYER_SPEED * RACKET_SPIN;
+        clamp(&mut offset, -1.0, 1.0);
+
+        let angle = offset * MAX_BOUNCE_ANGLE;
+        let speed = self.ball_vel.norm() + BALL_SPEEDUP;
+        self.ball_vel = na::Vector2::new(dir_x * speed * angle.cos(), speed * angle.sin());
+        self.last_hitter = Some(player);
+        events.push(Event::Hit(player));
+    }
+
+    /// Finds the first thing the ball will touch when moving along `motion`
+    fn first_contact(&self, motion: na::Vector2<f32>) -> Option<(f32, Contact)> {
+        let mut first: Option<(f32, Contact)> = None;
+        let mut consider = |t: f32, contact: Contact| {
+            if (0.0..=1.0).contains(&t) && first.map_or(true, |(first_t, _)| t < first_t) {
+                first = Some((t, contact));
+            }
+        };
+
+        if motion.y < 0.0 {
+            consider(
+                (BALL_SIZE_HALF - self.ball_pos.y) / motion.y,
+                Contact::Wall(1.0),
+            );
+        } else if motion.y > 0.0 {
+            let t = (self.field_h - BALL_SIZE_HALF - self.ball_pos.y) / motion.y;
+            consider(t, Contact::Wall(-1.0));
+        }
+
+        for &player in &[Player::One, Player::Two] {
+            if let Some((t, normal)) = sweep_racket(self.ball_pos, motion, self.racket_pos(player))
+            {
+                consider(t, Contact::Racket(player, normal));
+            }
+        }
+
+        first
+    }
+
+    /// Moves the ball for `dt` seconds, bouncing it off everything it touches on the way
+    fn move_ball(&mut self, dt: f32, events: &mut Vec<Event>) {
+        // a racket might have moved on top of the ball, just push it out the front
+        for &player in &[Player::One, Player::Two] {
+            let racket_pos = self.racket_pos(player);
+            if intersects_racket(self.ball_pos, racket_pos) {
+                self.ball_pos.x = match player {
+                    Player::One => racket_pos.x + RACKET_WIDTH_HALF + BALL_SIZE_HALF,
+                    Player::Two => racket_pos.x - RACKET_WIDTH_HALF - BALL_SIZE_HALF,
+                };
+                self.bounce_off_racket(player, events);
+            }
+        }
+
+        let mut remaining = dt;
+        for _ in 0..MAX_BOUNCES_PER_STEP {
+            let motion = self.ball_vel * remaining;
+            let (t, contact) = match self.first_contact(motion) {
+                Some(hit) => hit,
+                None => {
+                    self.ball_pos += motion;
+                    return;
+                }
+            };
+
+            // move up to the point of impact, bounce, and carry on with the time we have left
+            self.ball_pos += motion * t;
+            remaining -= remaining * t;
+            match contact {
+                Contact::Wall(normal_y) => {
+                    self.ball_vel.y = normal_y * self.ball_vel.y.abs();
+                    events.push(Event::Wall);
+                }
+                Contact::Racket(player, normal) => {
+                    if normal.x != 0.0 {
+                        self.bounce_off_racket(player, events);
+                    } else {
+                        // clipped the top or bottom of the racket
+                        self.ball_vel.y = normal.y * self.ball_vel.y.abs();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Advances the game by `dt` seconds
+    pub fn step(&mut self, input: Input, dt: f32) -> Vec<Event> {
+        let mut events = Vec::new();
+        // nothing can happen in no time, and the racket speeds would divide by zero
+        if dt.is_nan() || dt <= 0.0 {
+            return events;
+        }
+
+        let (player_1_y, player_2_y) = (self.player_1_pos.y, self.player_2_pos.y);
+        move_racket(&mut self.player_1_pos, input.player_1, self.field_h, dt);
+        move_racket(&mut self.player_2_pos, input.player_2, self.field_h, dt);
+        self.player_1_vel = (self.player_1_pos.y - player_1_y) / dt;
+        self.player_2_vel = (self.player_2_pos.y - player_2_y) / dt;
+
+        if self.winner.is_some() {
+            return events;
+        }
+
+        if self.serve_timer > 0.0 {
+            self.serve_timer -= dt;
+            if self.serve_timer > 0.0 {
+                return events;
+            }
+            self.serve_timer = 0.0;
+            self.serve();
+            events.push(Event::Serve);
+        }
+
+        self.move_ball(dt, &mut events);
+
+        // just in case the ball got squeezed out of the field somehow
+        clamp(
+            &mut self.ball_pos.y,
+            BALL_SIZE_HALF,
+            self.field_h - BALL_SIZE_HALF,
+        );
+
+        if self.ball_pos.x < 0.0 {
+            self.score_point(Player::Two, &mut events);
+        } else if self.ball_pos.x > self.field_w {
+            self.score_point(Player::One, &mut events);
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn sim() -> Simulation {
+        Simulation::with_seed(FIELD_W, FIELD_H, Ruleset::default(), 42)
+    }
+
+    /// A match with the ball already in play at `pos` going `vel`
+    fn in_play(pos: (f32, f32), vel: (f32, f32)) -> Simulation {
+        let mut sim = sim();
+        sim.serve_timer = 0.0;
+        sim.ball_pos = na::Point2::new(pos.0, pos.1);
+        sim.ball_vel = na::Vector2::new(vel.0, vel.1);
+        sim
+    }
+
+    #[test]
+    fn ball_moves_with_its_velocity() {
+        let mut sim = in_play((FIELD_W * 0.5, FIELD_H * 0.5), (300.0, -120.0));
+        assert!(sim.step(Input::default(), DT).is_empty());
+        assert_eq!(
+            sim.ball_pos,
+            na::Point2::new(FIELD_W * 0.5 + 300.0 * DT, FIELD_H * 0.5 - 120.0 * DT)
+        );
+    }
+
+    #[test]
+    fn serves_after_the_countdown() {
+        let mut sim = sim();
+        let middle = sim.ball_pos;
+        let mut served = false;
+        for _ in 0..(sim.rules.serve_countdown / DT) as usize + 2 {
+            served |= sim.step(Input::default(), DT).contains(&Event::Serve);
+        }
+        assert!(served);
+        assert_ne!(sim.ball_pos, middle);
+        assert!(sim.ball_vel.norm() > 0.0);
+    }
+
+    #[test]
+    fn ball_out_on_the_right_scores_for_player_one() {
+        let mut sim = in_play((FIELD_W - 2.0, 50.0), (300.0, 0.0));
+        let events = sim.step(Input::default(), DT);
+        assert!(events.contains(&Event::Goal(Player::One)));
+        assert_eq!(sim.scores, [1, 0]);
+        assert_eq!(sim.ball_pos, na::Point2::new(FIELD_W * 0.5, FIELD_H * 0.5));
+        assert!(sim.serve_timer > 0.0);
+    }
+
+    #[test]
+    fn ball_out_on_the_left_scores_for_player_two() {
+        let mut sim = in_play((2.0, 50.0), (-300.0, 0.0));
+        let events = sim.step(Input::default(), DT);
+        assert!(events.contains(&Event::Goal(Player::Two)));
+        assert_eq!(sim.scores, [0, 1]);
+    }
+
+    #[test]
+    fn winning_the_last_game_ends_the_match() {
+        let mut sim = in_play((2.0, 50.0), (-300.0, 0.0));
+        sim.scores = [0, 10];
+        let events = sim.step(Input::default(), DT);
+        assert!(events.contains(&Event::MatchWon(Player::Two)));
+        assert_eq!(sim.winner, Some(Player::Two));
+    }
+
+    #[test]
+    fn ball_bounces_off_the_walls() {
+        let mut sim = in_play((FIELD_W * 0.5, BALL_SIZE_HALF + 1.0), (0.0, -300.0));
+        sim.step(Input::default(), DT);
+        assert!(sim.ball_vel.y > 0.0);
+        assert!(sim.ball_pos.y >= BALL_SIZE_HALF);
+    }
+
+    #[test]
+    fn ball_bounces_off_a_racket() {
+        let racket = sim().player_1_pos;
+        let face = racket.x + RACKET_WIDTH_HALF + BALL_SIZE_HALF;
+        let mut sim = in_play((face + 1.0, racket.y), (-300.0, 0.0));
+        let events = sim.step(Input::default(), DT);
+        assert_eq!(events, vec![Event::Hit(Player::One)]);
+        assert!(sim.ball_vel.x > 0.0);
+    }
+
+    #[test]
+    fn stepping_no_time_changes_nothing() {
+        let mut sim = in_play((FIELD_W * 0.5, FIELD_H * 0.5), (300.0, 100.0));
+        let before = sim.clone();
+        let checksum = sim.checksum();
+        let input = Input {
+            player_1: 1.0,
+            player_2: -1.0,
+        };
+        assert!(sim.step(input, 0.0).is_empty());
+        assert!(sim.step(input, -DT).is_empty());
+        assert_eq!(sim.ball_pos, before.ball_pos);
+        assert_eq!(sim.player_1_pos, before.player_1_pos);
+        assert_eq!(sim.player_2_pos, before.player_2_pos);
+        assert!(sim.player_1_vel.is_finite() && sim.player_2_vel.is_finite());
+        assert_eq!(sim.checksum(), checksum);
+    }
+
+    #[test]
+    fn rackets_stay_on_the_field() {
+        let mut sim = sim();
+        let input = Input {
+            player_1: 1.0,
+            player_2: -1.0,
+        };
+        for _ in 0..600 {
+            sim.step(input, DT);
+        }
+        assert_eq!(sim.player_1_pos.y, RACKET_HEIGHT_HALF);
+        assert_eq!(sim.player_2_pos.y, FIELD_H - RACKET_HEIGHT_HALF);
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_checksum() {
+        let (mut a, mut b) = (sim(), sim());
+        assert_eq!(a.checksum(), b.checksum());
+        for i in 0..2000 {
+            let input = Input {
+                player_1: (i as f32 * 0.05).sin(),
+                player_2: (i as f32 * 0.03).cos(),
+            };
+            a.step(input, DT);
+            b.step(input, DT);
+            assert_eq!(a.checksum(), b.checksum());
+        }
+        b.step(
+            Input {
+                player_1: 1.0,
+                player_2: 0.0,
+            },
+            DT,
+        );
+        a.step(Input::default(), DT);
+        assert_ne!(a.checksum(), b.checksum());
+    }
+
+    #[test]
+    fn very_fast_ball_hits_the_racket_instead_of_tunnelling() {
+        // 100 pixels in one step, ten times the width of the racket
+        let mut sim = in_play((100.0, FIELD_H * 0.5), (-1e5, 0.0));
+        let events = sim.step(Input::default(), 0.001);
+        assert!(events.contains(&Event::Hit(Player::One)));
+        assert!(sim.ball_vel.x > 0.0);
+        assert!(sim.ball_pos.x > PADDING + RACKET_WIDTH + BALL_SIZE_HALF);
+        assert_eq!(sim.scores, [0, 0]);
+    }
+
+    #[test]
+    fn ball_clipping_the_top_of_a_racket_bounces_up() {
+        let racket = sim().player_1_pos;
+        let top = racket.y - RACKET_HEIGHT_HALF - BALL_SIZE_HALF;
+        let mut sim = in_play((racket.x, top - 50.0), (0.0, 600.0));
+        let events = sim.step(Input::default(), 0.1);
+        assert!(!events.contains(&Event::Hit(Player::One)));
+        assert!(sim.ball_vel.y < 0.0);
+        assert!(sim.ball_pos.y < top);
+    }
+
+    #[test]
+    fn ball_near_the_corner_of_a_racket_hits_its_face() {
+        let racket = sim().player_1_pos;
+        let face = racket.x + RACKET_WIDTH_HALF + BALL_SIZE_HALF;
+        let top = racket.y - RACKET_HEIGHT_HALF - BALL_SIZE_HALF;
+        // gets level with the top a little before reaching the face
+        let mut sim = in_play((face + 20.0, top - 17.0), (-600.0, 600.0));
+        let events = sim.step(Input::default(), 0.05);
+        assert!(events.contains(&Event::Hit(Player::One)));
+        assert!(sim.ball_vel.x > 0.0);
+        assert!(sim.ball_pos.x > face);
+    }
+
+    #[test]
+    fn wall_and_racket_in_the_same_step() {
+        let mut sim = in_play((75.0, 20.0), (-3600.0, -1200.0));
+        sim.player_1_pos.y = 60.0;
+        let events = sim.step(Input::default(), DT);
+        assert!(events.contains(&Event::Wall));
+        assert!(events.contains(&Event::Hit(Player::One)));
+        // came off the top wall on the way to the racket
+        assert!(sim.ball_vel.y > 0.0);
+        assert!(sim.ball_vel.x > 0.0);
+        assert_eq!(sim.scores, [0, 0]);
+    }
+
+    #[test]
+    fn absurd_speeds_still_end_the_step_on_the_field() {
+        // bounces between the walls far more often than MAX_BOUNCES_PER_STEP allows
+        let mut sim = in_play((FIELD_W * 0.5, FIELD_H * 0.5), (1.0, 1e7));
+        let events = sim.step(Input::default(), DT);
+        let walls = events.iter().filter(|&&event| event == Event::Wall).count();
+        assert!(walls <= MAX_BOUNCES_PER_STEP);
+        assert!(sim.ball_pos.y >= BALL_SIZE_HALF && sim.ball_pos.y <= FIELD_H - BALL_SIZE_HALF);
+    }
+}
@@ -0,0 +1,82 @@
+//! Keeping track of people watching a match. Both the dedicated server and a
+//! game hosting a match over `--host` let spectators in, using the server's
+//! packets on whatever socket the match is already on.
+
+use crate::protocol::{ClientMessage, ServerMessage, TIMEOUT};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Instant;
+
+/// More than this many and the host would spend its time sending snapshots
+pub const MAX_SPECTATORS: usize = 16;
+
+struct Spectator {
+    address: SocketAddr,
+    last_heard: Instant,
+}
+
+#[derive(Default)]
+pub struct Spectators {
+    watching: Vec<Spectator>,
+}
+
+impl Spectators {
+    pub fn new() -> Spectators {
+        Spectators::default()
+    }
+
+    /// How many people are watching
+    pub fn count(&self) -> u8 {
+        self.watching.len() as u8
+    }
+
+    /// Deals with a message from someone who isn't playing. `welcome` is what
+    /// spectators get told about the match.
+    pub fn receive(
+        &mut self,
+        socket: &UdpSocket,
+        from: SocketAddr,
+        message: &ClientMessage,
+        welcome: &ServerMessage,
+    ) {
+        let known = self.watching.iter().position(|s| s.address == from);
+        match (message, known) {
+            (ClientMessage::Watch, Some(index)) => {
+                self.watching[index].last_heard = Instant::now();
+                // answer every one in case the first answer got lost
+                let _ = socket.send_to(&welcome.encode(), from);
+            }
+            (ClientMessage::Watch, None) => {
+                if self.watching.len() >= MAX_SPECTATORS {
+                    let _ = socket.send_to(&ServerMessage::Full.encode(), from);
+                    return;
+                }
+                self.watching.push(Spectator {
+                    address: from,
+                    last_heard: Instant::now(),
+                });
+                let _ = socket.send_to(&welcome.encode(), from);
+            }
+            (ClientMessage::Leave, Some(index)) => {
+                self.watching.remove(index);
+            }
+            _ => (),
+        }
+    }
+
+    /// Forgets spectators that went quiet, gives back how many there were
+    pub fn drop_silent(&mut self) -> usize {
+        let before = self.watching.len();
+        self.watching
+            .retain(|spectator| spectator.last_heard.elapsed() <= TIMEOUT);
+        before - self.watching.len()
+    }
+
+    /// Sends `message` to everyone watching
+    pub fn send(&self, socket: &UdpSocket, message: &ServerMessage) {
+        let packet = message.encode();
+        for spectator in &self.watching {
+            // lost packets are fine, a fresh snapshot is never far behind
+            let _ = socket.send_to(&packet, spectator.address);
+        }
+    }
+}
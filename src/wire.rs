@@ -0,0 +1,168 @@
+//! Turning things into bytes for the network and back again.
+//! Everything is little endian and read back in the order it was written.
+
+use crate::rules::{Ruleset, ServeModel, ServeRule};
+use crate::simulation::Player;
+
+/// Builds up a packet
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn player(&mut self, player: Player) {
+        self.u8(player.index() as u8);
+    }
+
+    /// `None` is written as a byte of its own, so it can't be mistaken for a player
+    pub fn maybe_player(&mut self, player: Option<Player>) {
+        self.u8(player.map_or(0, |player| player.index() as u8 + 1));
+    }
+
+    pub fn rules(&mut self, rules: &Ruleset) {
+        self.u32(rules.win_score);
+        self.bool(rules.win_by_two);
+        self.u32(rules.best_of);
+        let serve = ServeRule::ALL.iter().position(|&s| s == rules.serve);
+        self.u8(serve.unwrap_or(0) as u8);
+        self.f32(rules.serve_countdown);
+        let model = &rules.serve_model;
+        self.f32(model.min_angle);
+        self.f32(model.max_angle);
+        self.f32(model.speed);
+        self.f32(model.speed_spread);
+        self.bool(model.toward_conceder);
+    }
+}
+
+/// Reads a packet back, every read gives `None` once the packet runs out
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes }
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < count {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Some(array)
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn i32(&mut self) -> Option<i32> {
+        Some(i32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn f32(&mut self) -> Option<f32> {
+        Some(f32::from_le_bytes(self.array()?))
+    }
+
+    pub fn bool(&mut self) -> Option<bool> {
+        Some(self.u8()? != 0)
+    }
+
+    pub fn player(&mut self) -> Option<Player> {
+        match self.u8()? {
+            0 => Some(Player::One),
+            1 => Some(Player::Two),
+            _ => None,
+        }
+    }
+
+    /// Reads what `Writer::maybe_player` wrote, the outer `None` means the packet was bad
+    pub fn maybe_player(&mut self) -> Option<Option<Player>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(Player::One)),
+            2 => Some(Some(Player::Two)),
+            _ => None,
+        }
+    }
+
+    pub fn rules(&mut self) -> Option<Ruleset> {
+        Some(Ruleset {
+            win_score: self.u32()?,
+            win_by_two: self.bool()?,
+            best_of: self.u32()?,
+            serve: *ServeRule::ALL.get(self.u8()? as usize)?,
+            serve_countdown: self.f32()?,
+            // whoever sent it might not have checked, and a broken serve could hang the match
+            serve_model: ServeModel {
+                min_angle: self.f32()?,
+                max_angle: self.f32()?,
+                speed: self.f32()?,
+                speed_spread: self.f32()?,
+                toward_conceder: self.bool()?,
+            }
+            .clamped(),
+        })
+    }
+}